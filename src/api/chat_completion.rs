@@ -1,10 +1,16 @@
 
 use derive_builder::Builder;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+
+use crate::error::LlmError;
+use crate::limiter::estimate_tokens;
+use crate::schema::{parse_arguments, ArgumentsError};
 /// https://www.volcengine.com/docs/82379/1298454#%E6%95%B0%E6%8D%AE%E7%BB%93%E6%9E%84
 
-#[derive(Serialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct ChatCompletionRequest {
     /// 您创建的推理接入点 ID, ep-202406040*****-*****
     model: String,
@@ -13,7 +19,7 @@ pub struct ChatCompletionRequest {
     /// 如果 message 列表中前文出现了带有 n 个 tool_calls 的 Assistant Message，
     /// 则后文必须有连续 n 个分别和每个 tool_call_id 相对应的 Tool Message，来回应 tool_calls 的信息要求
     #[builder(setter(into))]
-    messages: Vec<ChatCompletionMessage>,
+    pub(crate) messages: Vec<ChatCompletionMessage>,
     /// 响应内容是否流式返回
     /// false：模型生成完所有内容后一次性返回结果
     /// true：按 SSE 协议逐块返回模型生成内容，并以一条 data: [DONE] 消息结束
@@ -66,10 +72,64 @@ pub struct ChatCompletionRequest {
     /// 模型可以调用的工具列表。目前，仅函数作为工具被支持。用这个来提供模型可能为其生成 JSON 输入的函数列表。
     #[builder(default, setter(strip_option))]
     #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<ToolParam>>,
+    pub(crate) tools: Option<Vec<ToolParam>>,
+    /// 模型调用工具的方式，不设置时由模型自行决定（有 tools 时等同于 auto）
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    /// 是否允许模型在一条消息中同时调用多个工具
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+    /// 指定模型输出的格式，json_object 保证输出合法的 JSON，json_schema 还会按给定的 Schema 约束输出结构
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) response_format: Option<ResponseFormat>,
+    /// 深度思考模式，仅支持深度思考的模型生效
+    #[builder(default, setter(into, strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingParam>,
+}
+
+/// 不设置 max_tokens 时按默认的最大输出长度估算
+pub(crate) const DEFAULT_MAX_TOKENS: usize = 4096;
+/// 每条消息额外的格式开销
+pub(crate) const TOKENS_PER_MESSAGE: u32 = 4;
+
+impl ChatCompletionRequest {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn messages(&self) -> &[ChatCompletionMessage] {
+        &self.messages
+    }
+
+    /// 在消息列表末尾追加一条消息，多轮对话和工具调用时使用
+    pub fn push_message(&mut self, message: ChatCompletionMessage) {
+        self.messages.push(message);
+    }
+
+    pub fn tools(&self) -> Option<&[ToolParam]> {
+        self.tools.as_deref()
+    }
+
+    /// 预估本次请求消耗的 token 数（输入 + 最大输出），用于客户端限流
+    pub fn estimate_tokens(&self) -> u32 {
+        let prompt: u32 = self.messages.iter().map(ChatCompletionMessage::estimate_tokens).sum();
+        let tools: u32 = self
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| {
+                serde_json::to_string(&tool.function).map_or(0, |json| estimate_tokens(&json))
+            })
+            .sum();
+        prompt + tools + self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) as u32
+    }
 }
 
-#[derive(Serialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct ToolParam {
     /// 工具类型，当前仅支持 function
     pub r#type: String,
@@ -77,7 +137,7 @@ pub struct ToolParam {
     pub function: FunctionDefinition,
 }
 
-#[derive(Serialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct FunctionDefinition {
     /// 函数的名称, 比如：get_current_weather
     pub name: String,
@@ -91,7 +151,139 @@ pub struct FunctionDefinition {
     pub parameters: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Clone, Debug, Builder)]
+/// 是否开启深度思考
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkingType {
+    /// 开启，先输出思考过程再回答
+    Enabled,
+    /// 关闭，直接回答
+    Disabled,
+    /// 由模型根据问题难度自行判断
+    Auto,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThinkingParam {
+    pub r#type: ThinkingType,
+}
+
+impl From<ThinkingType> for ThinkingParam {
+    fn from(r#type: ThinkingType) -> Self {
+        Self { r#type }
+    }
+}
+
+/// 模型输出的格式
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ResponseFormat {
+    /// 普通文本，默认值
+    Text,
+    /// 输出合法的 JSON 对象，需要在提示词中说明期望的字段
+    JsonObject,
+    /// 按 JSON Schema 约束输出
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Builder)]
+pub struct JsonSchemaFormat {
+    /// 输出格式的名称
+    #[builder(setter(into))]
+    pub name: String,
+    /// 输出格式的描述，帮助模型理解用途
+    #[builder(default, setter(into, strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema
+    pub schema: serde_json::Value,
+    /// 是否严格按照 Schema 输出
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    pub fn json_schema(name: impl Into<String>, schema: serde_json::Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: name.into(),
+                description: None,
+                schema,
+                strict: Some(true),
+            },
+        }
+    }
+}
+
+/// 控制模型是否调用工具
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// 不调用工具，直接回复
+    None,
+    /// 由模型决定是否调用工具
+    Auto,
+    /// 必须调用至少一个工具
+    Required,
+    /// 必须调用指定名称的函数
+    Function(String),
+}
+
+impl ToolChoice {
+    pub fn function(name: impl Into<String>) -> Self {
+        ToolChoice::Function(name.into())
+    }
+}
+
+/// none、auto、required 序列化为字符串，指定函数时序列化为 {"type": "function", "function": {"name": "..."}}
+impl Serialize for ToolChoice {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => NamedToolChoice {
+                r#type: ToolCallType::Function,
+                function: NamedFunction { name: name.into() },
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ToolChoice {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr<'a> {
+            Mode(String),
+            Named(NamedToolChoice<'a>),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Mode(mode) => match mode.as_str() {
+                "none" => Ok(ToolChoice::None),
+                "auto" => Ok(ToolChoice::Auto),
+                "required" => Ok(ToolChoice::Required),
+                other => Err(serde::de::Error::unknown_variant(other, &["none", "auto", "required"])),
+            },
+            Repr::Named(named) => Ok(ToolChoice::Function(named.function.name.into_owned())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NamedToolChoice<'a> {
+    r#type: ToolCallType,
+    function: NamedFunction<'a>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NamedFunction<'a> {
+    name: Cow<'a, str>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct StreamOptionsParam {
     /// 是否包含本次请求的 token 用量统计信息
     /// false：不返回 token 用量信息
@@ -102,7 +294,7 @@ pub struct StreamOptionsParam {
 }
 
 #[allow(dead_code)]
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case", tag = "role")]
 pub enum ChatCompletionMessage {
     /// System Message 系统消息
@@ -115,62 +307,107 @@ pub enum ChatCompletionMessage {
     Tool(ToolMessage),
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl ChatCompletionMessage {
+    /// 预估这条消息占用的输入 token 数，包含每条消息的格式开销
+    pub fn estimate_tokens(&self) -> u32 {
+        let text_tokens = match self {
+            ChatCompletionMessage::System(m) => estimate_tokens(&m.content),
+            ChatCompletionMessage::User(m) => estimate_tokens(&m.content),
+            ChatCompletionMessage::Assistant(m) => {
+                m.content.as_deref().map_or(0, estimate_tokens)
+                    + m.tool_calls.iter().flatten().map(|call| {
+                        estimate_tokens(&call.function.name)
+                            + estimate_tokens(&call.function.arguments)
+                    }).sum::<u32>()
+            }
+            ChatCompletionMessage::Tool(m) => estimate_tokens(&m.content),
+        };
+        text_tokens + TOKENS_PER_MESSAGE
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SystemMessage {
     /// 消息内容
     pub content: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserMessage {
     /// 消息内容
     pub content: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AssistantMessage {
     /// 消息内容
     pub content: Option<String>,
     pub tool_calls: Option<Vec<MessageToolCallParam>>,
 }
 
-#[derive(Serialize, Clone, Debug)]
-
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MessageToolCallParam {
     /// 当前工具调用 ID
-    id: String,
+    pub id: String,
     /// 工具类型，当前仅支持function
-    r#type: ToolCallType,
+    pub r#type: ToolCallType,
     /// 当前工具调用参数
-    function: FunctionParam,
+    pub function: FunctionParam,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FunctionParam {
     /// 模型需要调用的函数名称
-    name: String,
+    pub name: String,
     /// 模型生成的用于调用函数的参数，JSON 格式。请注意，模型并不总是生成有效的 JSON，并且可能会虚构出一些您的函数参数规范中未定义的参数。在调用函数之前，请在您的代码中验证这些参数是否有效。
-    arguments: String,
+    pub arguments: String,
 }
 
-#[derive(Serialize, Clone, Debug, Default, Copy)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum ToolCallType {
     #[default]
     Function,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ToolMessage {
     /// 消息内容
-    content: String,
+    pub content: String,
     /// 此消息所回应的工具调用 ID，当 role 为 tool 时必填
-    tool_call_id: String,
+    pub tool_call_id: String,
+}
+
+impl From<&MessageToolCall> for MessageToolCallParam {
+    fn from(call: &MessageToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            r#type: ToolCallType::Function,
+            function: FunctionParam {
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone(),
+            },
+        }
+    }
+}
+
+/// 把模型返回的消息转换成请求中的 Assistant Message，用于多轮对话。
+/// 思考过程 reasoning_content 不需要再传给模型，转换时丢弃
+impl From<&Message> for AssistantMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            content: message.content.clone(),
+            tool_calls: message
+                .tool_calls
+                .as_ref()
+                .map(|calls| calls.iter().map(MessageToolCallParam::from).collect()),
+        }
+    }
 }
 
 ////////////////////////////  Response  //////////////////////
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionResponse {
     /// 本次请求的唯一标识
     pub id: String,
@@ -186,8 +423,39 @@ pub struct ChatCompletionResponse {
     pub usage: Option<Usage>,
 }
 
+impl ChatCompletionResponse {
+    /// 第一个 choice 的文本内容，只有工具调用时为 None
+    pub fn text(&self) -> Option<&str> {
+        self.choices.first()?.message.content.as_deref()
+    }
+
+    /// 把第一个 choice 的 content 按 JSON 解析成 T，会去掉模型有时包在外面的 ```json 代码块
+    pub fn parse_content<T: DeserializeOwned>(&self) -> crate::Result<T> {
+        let choice = self.choices.first();
+        let text = choice
+            .and_then(|choice| choice.message.content.as_deref())
+            .unwrap_or_default();
+        serde_json::from_str(strip_code_fence(text)).map_err(|source| LlmError::Output {
+            source,
+            text: text.to_string(),
+            finish_reason: choice.map(|choice| choice.finish_reason.clone()),
+        })
+    }
+}
+
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    match text.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.strip_prefix("json").unwrap_or(rest);
+            rest.strip_suffix("```").unwrap_or(rest).trim()
+        }
+        None => text,
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Choice {
     /// 当前元素在 choices 列表的索引
     pub index: usize,
@@ -204,18 +472,21 @@ pub struct Choice {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     /// 固定为 assistant
     pub role: String,
     /// 模型生成的消息内容，content 与 tool_calls 字段二者至少有一个为非空
     pub content: Option<String>,
+    /// 深度思考模型输出的思考过程
+    #[serde(default)]
+    pub reasoning_content: Option<String>,
     /// 模型生成的消息内容，content 与 tool_calls 字段二者至少有一个为非空
     pub tool_calls: Option<Vec<MessageToolCall>>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageToolCall {
     /// 当前工具调用 ID
     pub id: String,
@@ -226,7 +497,7 @@ pub struct MessageToolCall {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     /// 模型需要调用的函数名称
     pub name: String,
@@ -234,39 +505,46 @@ pub struct Function {
     pub arguments: String,
 }
 
+impl Function {
+    /// 把 arguments 解析成参数类型 T，参数类型加上 `#[serde(deny_unknown_fields)]` 后可以拒绝模型虚构的字段
+    pub fn parse_arguments<T: DeserializeOwned>(&self) -> Result<T, ArgumentsError> {
+        parse_arguments(&self.arguments)
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceLogprobs {
     /// message列表中每个 content 元素中的 token 对数概率信息
-    content: Vec<TokenLogprob>,
+    pub content: Vec<TokenLogprob>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenLogprob {
     /// 当前 token
-    token: String,
+    pub token: String,
     /// 当前 token 的 UTF-8 值，格式为整数列表。当一个字符由多个 token 组成（表情符号或特殊字符等）时可以用于字符的编码和解码。如果 token 没有 UTF-8 值则为空。
-    bytes: Vec<usize>,
+    pub bytes: Vec<usize>,
     /// 当前 token 的对数概率
-    logprob: f32,
+    pub logprob: f32,
     /// 在当前 token 位置最有可能的标记及其对数概率的列表。在一些情况下，返回的数量可能比请求参数 top_logprobs 指定的数量要少。
-    top_logprobs: Option<Vec<TopLogprob>>,
+    pub top_logprobs: Option<Vec<TopLogprob>>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TopLogprob {
     /// 当前 token
-    token: String,
+    pub token: String,
     /// 当前 token 的 UTF-8 值，格式为整数列表。当一个字符由多个 token 组成（表情符号或特殊字符等）时可以用于字符的编码和解码。如果 token 没有 UTF-8 值则为空。
-    bytes: Vec<usize>,
+    pub bytes: Vec<usize>,
     /// 当前 token 的对数概率
-    logprob: f32,
+    pub logprob: f32,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     /// 输入的 prompt token 数量
     pub prompt_tokens: usize,
@@ -274,75 +552,124 @@ pub struct Usage {
     pub completion_tokens: usize,
     /// 本次请求消耗的总 token 数量（输入 + 输出）
     pub total_tokens: usize,
+    /// 输出 token 的明细
+    #[serde(default)]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+impl Usage {
+    /// 思考过程消耗的 token 数，包含在 completion_tokens 中
+    pub fn reasoning_tokens(&self) -> usize {
+        self.completion_tokens_details
+            .as_ref()
+            .map_or(0, |details| details.reasoning_tokens)
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompletionTokensDetails {
+    /// 思考过程消耗的 token 数
+    #[serde(default)]
+    pub reasoning_tokens: usize,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionResponseWrapper {
     /// 豆包返回多了一个data
-    data: ChatCompletionChunkResponse,
+    pub data: ChatCompletionChunkResponse,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunkResponse {
     /// 本次请求的唯一标识
-    id: String,
+    pub id: String,
     /// 本次请求实际使用的模型名称和版本
-    model: String,
+    pub model: String,
     /// 固定为 chat.completion(非流式)，固定为 chat.completion.chunk（流式）
-    object: String,
+    pub object: String,
     /// 本次请求创建时间的 Unix 时间戳（秒）
-    created: i64,
+    pub created: i64,
     /// 本次请求的模型输出内容
-    choices: Vec<StreamChoice>,
+    pub choices: Vec<StreamChoice>,
     /// 本次请求的 tokens 用量
-    usage: Option<Usage>,
+    pub usage: Option<Usage>,
+}
+
+impl ChatCompletionChunkResponse {
+    /// 第一个 choice 在这个数据块中新增的文本，只有思考过程或工具调用片段时为 None
+    pub fn delta_text(&self) -> Option<&str> {
+        self.choices.first()?.delta.as_ref()?.content.as_deref()
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize, Builder)]
+#[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 pub struct StreamChoice {
     /// 当前元素在 choices 列表的索引
-    index: usize,
+    pub index: usize,
     /// stop：模型输出自然结束，或因命中请求参数 stop 中指定的字段而被截断
     /// length：模型输出因达到请求参数 max_token 指定的最大 token 数量而被截断
     /// content_filter：模型输出被内容审核拦截
     /// tool_calls：模型调用了工具
     #[builder(setter(strip_option))]
-    finish_reason: Option<String>,
+    pub finish_reason: Option<String>,
     /// 模型输出的内容
     #[builder(setter(strip_option))]
-    delta: Option<ChoiceDelta>,
+    pub delta: Option<ChoiceDelta>,
     /// 当前内容的对数概率信息
     #[builder(setter(strip_option))]
-    logprobs: Option<ChoiceLogprobs>,
+    pub logprobs: Option<ChoiceLogprobs>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize, Builder)]
+#[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 pub struct ChoiceDelta {
-    /// 固定为 assistant
-    role: String,
+    /// 固定为 assistant，只在第一个数据块中出现
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub role: Option<String>,
     /// 模型生成的消息内容，content 与 tool_calls 字段二者至少有一个为非空
     #[builder(setter(strip_option))]
-    content: Option<String>,
+    pub content: Option<String>,
+    /// 深度思考模型输出的思考过程片段，先于 content 输出
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub reasoning_content: Option<String>,
     /// 模型生成的消息内容，content 与 tool_calls 字段二者至少有一个为非空
     #[builder(setter(strip_option))]
-    tool_calls: Option<Vec<ChoiceDeltaToolCall>>,
+    pub tool_calls: Option<Vec<ChoiceDeltaToolCall>>,
 }
 
+/// 流式输出中的工具调用片段。
+/// id、type 和函数名只在该工具调用的第一个片段中出现，arguments 分散在多个片段中，需要按 index 拼接
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceDeltaToolCall {
     /// 当前元素在 tool_calls 列表的索引
-    index: usize,
+    pub index: usize,
     /// 当前工具调用 ID
-    id: String,
+    #[serde(default)]
+    pub id: Option<String>,
     /// 工具类型，当前仅支持function
-    r#type: String,
-    /// 当前工具调用参数
-    function: Function,
+    #[serde(default)]
+    pub r#type: Option<String>,
+    /// 当前工具调用参数片段
+    #[serde(default)]
+    pub function: Option<FunctionDelta>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDelta {
+    /// 模型需要调用的函数名称
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 函数参数的一段 JSON 文本
+    #[serde(default)]
+    pub arguments: Option<String>,
 }
 
 #[cfg(test)]
@@ -375,6 +702,152 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tool_choice_should_serialize() {
+        let request = |tool_choice: ToolChoice| {
+            let request = ChatCompletionRequestBuilder::default()
+                .model("ep-20240817170913-w9q57".to_string())
+                .messages(vec![ChatCompletionMessage::User(UserMessage {
+                    content: "北京天气怎么样".to_string(),
+                })])
+                .tool_choice(tool_choice)
+                .parallel_tool_calls(false)
+                .build()
+                .unwrap();
+            serde_json::to_value(&request).unwrap()
+        };
+        assert_eq!(request(ToolChoice::None)["tool_choice"], "none");
+        assert_eq!(request(ToolChoice::Auto)["tool_choice"], "auto");
+        assert_eq!(request(ToolChoice::Required)["tool_choice"], "required");
+        assert_eq!(request(ToolChoice::Required)["parallel_tool_calls"], false);
+        assert_eq!(
+            serde_json::to_string(&ToolChoice::function("get_current_weather")).unwrap(),
+            r#"{"type":"function","function":{"name":"get_current_weather"}}"#
+        );
+
+        let request = ChatCompletionRequestBuilder::default()
+            .model("ep-20240817170913-w9q57".to_string())
+            .messages(vec![])
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(!json.contains("tool_choice") && !json.contains("parallel_tool_calls"));
+    }
+
+    #[test]
+    fn response_with_multiple_tool_calls_should_parse() {
+        let json = r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":"","tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"北京\"}"}},{"id":"call_2","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"上海\"}"}}]}}],"usage":{"prompt_tokens":50,"completion_tokens":20,"total_tokens":70}}"#;
+        let res: ChatCompletionResponse = serde_json::from_str(json).unwrap();
+        let calls = res.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].id, "call_2");
+        assert_eq!(calls[1].function.arguments, r#"{"location":"上海"}"#);
+
+        // 转换回请求中的 Assistant Message 时保留全部调用和顺序
+        let message = AssistantMessage::from(&res.choices[0].message);
+        let json = serde_json::to_value(ChatCompletionMessage::Assistant(message)).unwrap();
+        assert_eq!(json["tool_calls"][0]["id"], "call_1");
+        assert_eq!(json["tool_calls"][1]["function"]["name"], "get_current_weather");
+    }
+
+    #[test]
+    fn reasoning_fields_should_work() {
+        let request = ChatCompletionRequestBuilder::default()
+            .model("ep-20250310183032-lljzt".to_string())
+            .messages(vec![])
+            .thinking(ThinkingType::Disabled)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"model":"ep-20250310183032-lljzt","messages":[],"thinking":{"type":"disabled"}}"#
+        );
+
+        let json = r#"{"id":"0217","model":"doubao-1-5-thinking-pro-250415","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"我是豆包","reasoning_content":"用户在问我是谁"}}],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42,"completion_tokens_details":{"reasoning_tokens":24}}}"#;
+        let res: ChatCompletionResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(res.choices[0].message.reasoning_content.as_deref(), Some("用户在问我是谁"));
+        assert_eq!(res.usage.as_ref().unwrap().reasoning_tokens(), 24);
+        assert_eq!(serde_json::from_str::<Usage>(r#"{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}"#).unwrap().reasoning_tokens(), 0);
+    }
+
+    #[test]
+    fn text_accessors_should_work() {
+        let json = r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"我是豆包"}}]}"#;
+        let res: ChatCompletionResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(res.text(), Some("我是豆包"));
+
+        let chunk = |delta: &str| -> ChatCompletionChunkResponse {
+            serde_json::from_str(&format!(
+                r#"{{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{{"index":0,"delta":{}}}]}}"#,
+                delta
+            ))
+            .unwrap()
+        };
+        let first = chunk(r#"{"role":"assistant","content":"我"}"#);
+        assert_eq!(first.delta_text(), Some("我"));
+        assert_eq!(first.choices[0].delta.as_ref().unwrap().role.as_deref(), Some("assistant"));
+        assert_eq!(chunk(r#"{"reasoning_content":"嗯"}"#).delta_text(), None);
+        let last: ChatCompletionChunkResponse = serde_json::from_str(r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#).unwrap();
+        assert_eq!(last.delta_text(), None);
+        assert_eq!(last.usage.unwrap().total_tokens, 2);
+    }
+
+    #[test]
+    fn chat_completion_request_estimate_tokens_should_work() {
+        let request = ChatCompletionRequestBuilder::default()
+            .model("ep-20240817170913-w9q57".to_string())
+            .messages(vec![
+                ChatCompletionMessage::System(SystemMessage {
+                    content: "你好".to_string(),
+                }),
+                ChatCompletionMessage::User(UserMessage {
+                    content: "who are you?".to_string(),
+                }),
+            ])
+            .max_tokens(100)
+            .build()
+            .unwrap();
+        // 2 + 3 个文本 token，每条消息 4 个额外 token，再加上 max_tokens
+        assert_eq!(request.estimate_tokens(), 2 + 3 + 4 * 2 + 100);
+    }
+
+    /// 序列化后再反序列化，两次得到的 JSON 必须一致
+    fn round_trip<T: Serialize + DeserializeOwned>(json: &str) -> serde_json::Value {
+        let first = serde_json::to_value(serde_json::from_str::<T>(json).unwrap()).unwrap();
+        let second = serde_json::to_value(serde_json::from_value::<T>(first.clone()).unwrap()).unwrap();
+        assert_eq!(first, second);
+        first
+    }
+
+    #[test]
+    fn request_should_round_trip() {
+        let json = r#"{"model":"ep-20240817170913-w9q57","messages":[{"role":"system","content":"你是天气助手"},{"role":"user","content":"北京天气怎么样"},{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"北京\"}"}}]},{"role":"tool","content":"晴，25 度","tool_call_id":"call_1"}],"stream":true,"stream_options":{"include_usage":true},"max_tokens":100,"temperature":0.5,"logit_bias":{"1024":-100},"tools":[{"type":"function","function":{"name":"get_current_weather","description":"获取指定城市的天气","parameters":{"type":"object","properties":{"location":{"type":"string"}}}}}],"tool_choice":{"type":"function","function":{"name":"get_current_weather"}},"parallel_tool_calls":false,"response_format":{"type":"json_schema","json_schema":{"name":"weather","schema":{"type":"object"},"strict":true}},"thinking":{"type":"auto"}}"#;
+        let value = round_trip::<ChatCompletionRequest>(json);
+        assert_eq!(value, serde_json::from_str::<serde_json::Value>(json).unwrap());
+
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.tool_choice, Some(ToolChoice::function("get_current_weather")));
+        assert_eq!(request.response_format, Some(ResponseFormat::json_schema("weather", serde_json::json!({"type": "object"}))));
+        assert!(matches!(request.messages()[3], ChatCompletionMessage::Tool(ref m) if m.tool_call_id == "call_1"));
+
+        for tool_choice in [r#""none""#, r#""auto""#, r#""required""#] {
+            assert_eq!(serde_json::to_string(&serde_json::from_str::<ToolChoice>(tool_choice).unwrap()).unwrap(), tool_choice);
+        }
+        assert!(serde_json::from_str::<ToolChoice>(r#""any""#).is_err());
+        assert_eq!(serde_json::from_str::<ResponseFormat>(r#"{"type":"json_object"}"#).unwrap(), ResponseFormat::JsonObject);
+    }
+
+    #[test]
+    fn response_should_round_trip() {
+        let json = r#"{"id":"0217","model":"doubao-1-5-thinking-pro-250415","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":"","reasoning_content":"需要先查天气","tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"北京\"}"}}]},"logprobs":{"content":[{"token":"我","bytes":[230,136,145],"logprob":-0.5,"top_logprobs":[{"token":"我","bytes":[230,136,145],"logprob":-0.5}]}]}}],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42,"completion_tokens_details":{"reasoning_tokens":24}}}"#;
+        let value = round_trip::<ChatCompletionResponse>(json);
+        assert_eq!(value, serde_json::from_str::<serde_json::Value>(json).unwrap());
+
+        let chunk = r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"","reasoning_content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":""}}]},"logprobs":null}],"usage":null}"#;
+        let value = round_trip::<ChatCompletionChunkResponse>(chunk);
+        assert_eq!(value, serde_json::from_str::<serde_json::Value>(chunk).unwrap());
+    }
+
     #[tokio::test]
     async fn simple_chat_completion_should_work() -> Result<()> {
         let req = ChatCompletionRequestBuilder::default()
@@ -1,8 +1,14 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use derive_builder::Builder;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+use crate::limiter::estimate_tokens;
 
 #[allow(dead_code)]
-#[derive(Serialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct EmbeddingsRequest {
     /// 您创建的推理接入点 ID, ep-202406040*****-*****
     model: String,
@@ -11,48 +17,129 @@ pub struct EmbeddingsRequest {
     /// 2. 不能为空列表，列表的每个成员不能为空字符串
     /// 3. 单条文本以 utf-8 编码，长度不超过 100,000 字节
     /// 4. 文本数量不超过 256 条
-    input: Vec<String>,
-    /// embedding 返回的格式，当前支持 float或base64，默认为float
-    #[builder(default, setter(strip_option))]
+    pub(crate) input: Vec<String>,
+    /// embedding 返回的格式，当前支持 float或base64，不传时服务端按 float 返回。
+    /// builder 默认使用 base64，响应体积比 float 的 JSON 小很多，解析时自动解码
+    #[builder(default = "Some(EncodingFormat::Base64)", setter(strip_option))]
     #[serde(skip_serializing_if = "Option::is_none")]
-    encoding_format: Option<String>
+    encoding_format: Option<EncodingFormat>
+}
+
+/// embedding 返回的格式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingFormat {
+    /// 浮点数数组
+    Float,
+    /// 小端序 f32 数组的 base64 编码
+    Base64,
+}
+
+impl EmbeddingsRequest {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn input(&self) -> &[String] {
+        &self.input
+    }
+
+    pub fn encoding_format(&self) -> Option<EncodingFormat> {
+        self.encoding_format
+    }
+
+    /// 预估本次请求消耗的 token 数，用于客户端限流
+    pub fn estimate_tokens(&self) -> u32 {
+        self.input.iter().map(|text| estimate_tokens(text)).sum()
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct EmbeddingsResponse {
     /// 本次请求的唯一标识
-    id: String,
+    pub id: String,
     /// 本次请求实际使用的模型名称和版本
-    model: String,
+    pub model: String,
     /// 本次请求创建时间的 Unix 时间戳（秒）
-    created: usize,
+    pub created: usize,
     /// 固定为 list
-    object: String,
+    pub object: String,
     /// 本次请求的算法输出内容
-    data: Vec<Embedding>,
+    pub data: Vec<Embedding>,
     /// 本次请求的 tokens 用量
-    usage: Usage
+    pub usage: Usage
+}
+
+impl EmbeddingsResponse {
+    /// 按 index 排序的向量，与请求参数 input 的顺序一一对应
+    pub fn vectors(&self) -> Vec<&[f32]> {
+        let mut data: Vec<&Embedding> = self.data.iter().collect();
+        data.sort_by_key(|embedding| embedding.index);
+        data.into_iter().map(|embedding| embedding.embedding.as_slice()).collect()
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct Embedding {
     /// 向量的序号，与请求参数 input 列表中的内容顺序对应
-    index: usize,
-    /// 对应内容的向量化结果,
-    embedding: Vec<f32>,
+    pub index: usize,
+    /// 对应内容的向量化结果，encoding_format 为 base64 时解析响应时已解码
+    #[serde(deserialize_with = "deserialize_embedding")]
+    pub embedding: Vec<f32>,
     /// 固定为 embedding
-    object: String
+    pub object: String
+}
+
+/// embedding 可能是浮点数数组，也可能是 base64 字符串
+pub(crate) fn deserialize_embedding<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+    struct EmbeddingVisitor;
+
+    impl<'de> Visitor<'de> for EmbeddingVisitor {
+        type Value = Vec<f32>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an array of floats or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, encoded: &str) -> Result<Self::Value, E> {
+            decode_base64(encoded).map_err(E::custom)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut embedding = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(value) = seq.next_element()? {
+                embedding.push(value);
+            }
+            Ok(embedding)
+        }
+    }
+
+    deserializer.deserialize_any(EmbeddingVisitor)
+}
+
+/// 把 base64 编码的小端序 f32 数组解码成向量
+fn decode_base64(encoded: &str) -> Result<Vec<f32>, String> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("invalid base64 embedding: {}", e))?;
+    if bytes.len() % 4 != 0 {
+        return Err(format!("base64 embedding length {} is not a multiple of 4", bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct Usage {
     /// 输入的 prompt token 数量
-    prompt_tokens: u32,
+    pub prompt_tokens: u32,
     /// 本次请求消耗的总 token 数量（输入 + 输出)
-    total_tokens: u32
+    pub total_tokens: u32
 }
 
 
@@ -78,4 +165,65 @@ mod tests {
         assert_eq!(resp.data[0].index, 1);
     }
 
+    #[test]
+    fn embeddings_should_round_trip() {
+        let request = EmbeddingsRequestBuilder::default()
+            .model("ep-20241023154013-pzht4".to_string())
+            .input(vec![String::from("天很蓝"), String::from("海很深")])
+            .encoding_format(EncodingFormat::Float)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(serde_json::to_string(&serde_json::from_str::<EmbeddingsRequest>(&json).unwrap()).unwrap(), json);
+
+        // example.txt 是一次真实的 embeddings 响应
+        let fixture = include_str!("../../example.txt");
+        let res: EmbeddingsResponse = serde_json::from_str(fixture).unwrap();
+        let json = serde_json::to_string(&res).unwrap();
+        let again: EmbeddingsResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&again).unwrap(), json);
+
+        let original: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        for key in ["id", "model", "created", "object", "usage"] {
+            assert_eq!(value[key], original[key]);
+        }
+        assert_eq!(again.vectors().len(), 2);
+        assert_eq!(again.vectors()[0].len(), 4096);
+        for (embedding, expected) in again.data.iter().zip(original["data"].as_array().unwrap()) {
+            assert_eq!(embedding.index, expected["index"].as_u64().unwrap() as usize);
+            let expected: Vec<f32> = expected["embedding"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_f64().unwrap() as f32)
+                .collect();
+            assert_eq!(embedding.embedding, expected);
+        }
+    }
+
+    #[test]
+    fn base64_embedding_should_decode() {
+        let request = EmbeddingsRequestBuilder::default()
+            .model("ep-20241023154013-pzht4".to_string())
+            .input(vec![String::from("天很蓝")])
+            .build()
+            .unwrap();
+        assert_eq!(request.encoding_format(), Some(EncodingFormat::Base64));
+        assert!(serde_json::to_string(&request).unwrap().ends_with(r#""encoding_format":"base64"}"#));
+
+        let fixture: EmbeddingsResponse = serde_json::from_str(include_str!("../../example.txt")).unwrap();
+        let expected = fixture.vectors()[0];
+        let bytes: Vec<u8> = expected.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let json = format!(
+            r#"{{"id":"0217","model":"doubao-embedding-large-text-240915","created":1729671495,"object":"list","data":[{{"index":0,"embedding":"{}","object":"embedding"}}],"usage":{{"prompt_tokens":3,"total_tokens":3}}}}"#,
+            STANDARD.encode(&bytes)
+        );
+        assert!(json.len() < serde_json::to_string(&expected).unwrap().len());
+        let res: EmbeddingsResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(res.vectors()[0], expected);
+
+        let err = serde_json::from_str::<Embedding>(r#"{"index":0,"embedding":"AAAA","object":"embedding"}"#).unwrap_err();
+        assert!(err.to_string().contains("not a multiple of 4"));
+    }
 }
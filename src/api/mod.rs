@@ -1,6 +1,8 @@
 pub mod chat_completion;
 pub mod vision_lite;
 pub mod embeddings;
+pub mod multimodal_embeddings;
 pub mod vision_pro;
 
 
+pub mod stream_accumulator;
@@ -0,0 +1,146 @@
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+
+use super::embeddings::{deserialize_embedding, EncodingFormat};
+use super::vision_pro::Content;
+
+/// 多模态向量化请求，doubao-embedding-vision 等模型把全部输入（文本和图片）融合成一个向量
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
+pub struct MultimodalEmbeddingsRequest {
+    /// 您创建的推理接入点 ID, ep-202406040*****-*****
+    model: String,
+    /// 需要向量化的内容列表，每个元素为一段文本或一张图片。
+    /// 图片可以是 URL，也可以用 `Content::image_file` 读取本地文件生成 Data URL
+    #[builder(setter(into))]
+    input: Vec<Content>,
+    /// embedding 返回的格式，当前支持 float或base64，不传时服务端按 float 返回。
+    /// builder 默认使用 base64，解析时自动解码
+    #[builder(default = "Some(EncodingFormat::Base64)", setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding_format: Option<EncodingFormat>,
+}
+
+impl MultimodalEmbeddingsRequest {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn input(&self) -> &[Content] {
+        &self.input
+    }
+
+    pub fn encoding_format(&self) -> Option<EncodingFormat> {
+        self.encoding_format
+    }
+
+    /// 预估本次请求消耗的 token 数，用于客户端限流
+    pub fn estimate_tokens(&self) -> u32 {
+        self.input.iter().map(Content::estimate_tokens).sum()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultimodalEmbeddingsResponse {
+    /// 本次请求的唯一标识
+    pub id: String,
+    /// 本次请求实际使用的模型名称和版本
+    pub model: String,
+    /// 本次请求创建时间的 Unix 时间戳（秒）
+    pub created: usize,
+    /// 固定为 list
+    pub object: String,
+    /// 全部输入融合后的向量
+    pub data: MultimodalEmbedding,
+    /// 本次请求的 tokens 用量
+    pub usage: MultimodalUsage,
+}
+
+impl MultimodalEmbeddingsResponse {
+    pub fn vector(&self) -> &[f32] {
+        &self.data.embedding
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultimodalEmbedding {
+    /// 向量化结果，encoding_format 为 base64 时解析响应时已解码
+    #[serde(deserialize_with = "deserialize_embedding")]
+    pub embedding: Vec<f32>,
+    /// 固定为 embedding
+    pub object: String,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultimodalUsage {
+    /// 输入的 prompt token 数量
+    pub prompt_tokens: u32,
+    /// 本次请求消耗的总 token 数量
+    pub total_tokens: u32,
+    /// 输入 token 的明细
+    #[serde(default)]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PromptTokensDetails {
+    /// 文本消耗的 token 数
+    #[serde(default)]
+    pub text_tokens: u32,
+    /// 图片消耗的 token 数
+    #[serde(default)]
+    pub image_tokens: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::ESTIMATED_IMAGE_TOKENS;
+    use crate::tests::{http_response, mock_sdk, mock_server};
+
+    const RESPONSE: &str = r#"{"id":"0217","model":"doubao-embedding-vision-241215","created":1743575029,"object":"list","data":{"embedding":[0.5,-1.25,3.0],"object":"embedding"},"usage":{"prompt_tokens":528,"total_tokens":528,"prompt_tokens_details":{"text_tokens":13,"image_tokens":515}}}"#;
+
+    #[test]
+    fn request_should_serialize_with_local_image() {
+        let path = std::env::temp_dir().join(format!("llm-sdk-image-{}.PNG", std::process::id()));
+        std::fs::write(&path, b"\x89PNG").unwrap();
+        let image = Content::image_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let request = MultimodalEmbeddingsRequestBuilder::default()
+            .model("ep-20250402143024-xxxxx".to_string())
+            .input(vec![Content::text("天很蓝"), image.unwrap()])
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"model":"ep-20250402143024-xxxxx","input":[{"type":"text","text":"天很蓝"},{"type":"image_url","image_url":{"url":"data:image/png;base64,iVBORw=="}}],"encoding_format":"base64"}"#
+        );
+        assert_eq!(request.estimate_tokens(), 3 + ESTIMATED_IMAGE_TOKENS);
+        assert!(matches!(Content::image_file("cat.svg"), Err(crate::LlmError::InvalidInput(_))));
+        let err = Content::image_file("no-such-cat.png").unwrap_err();
+        assert!(matches!(err, crate::LlmError::InvalidInput(ref msg) if msg.contains("no-such-cat.png")));
+    }
+
+    #[tokio::test]
+    async fn multimodal_embeddings_should_work() {
+        let (base_url, requests) = mock_server(vec![http_response(200, &[], RESPONSE)]).await;
+        let request = MultimodalEmbeddingsRequestBuilder::default()
+            .model("ep-20250402143024-xxxxx".to_string())
+            .input(vec![Content::text("天很蓝"), Content::image_url("https://example.com/sky.png")])
+            .build()
+            .unwrap();
+        let res = mock_sdk(&base_url).multimodal_embeddings(&request).await.unwrap();
+        assert_eq!(res.vector(), [0.5, -1.25, 3.0]);
+        assert_eq!(res.usage.prompt_tokens_details.unwrap().image_tokens, 515);
+        assert!(requests.lock().unwrap()[0].starts_with("POST /api/v3/embeddings/multimodal HTTP/1.1"));
+
+        // base64 格式的响应同样可以解析
+        let json = RESPONSE.replace("[0.5,-1.25,3.0]", r#""AAAAPwAAoL8AAEBA""#);
+        let res: MultimodalEmbeddingsResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(res.vector(), [0.5, -1.25, 3.0]);
+    }
+}
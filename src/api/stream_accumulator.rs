@@ -0,0 +1,204 @@
+use futures::{Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::pin::pin;
+
+use super::chat_completion::{
+    ChatCompletionChunkResponse, ChatCompletionResponse, Choice, ChoiceLogprobs, Function, Message,
+    MessageToolCall, TokenLogprob, Usage,
+};
+use crate::error::Result;
+
+/// 把流式输出的数据块合并成一个完整的 ChatCompletionResponse。
+/// 按 choice 的 index 合并 content，按 tool call 的 index 拼接函数名和参数片段。
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    id: String,
+    model: String,
+    created: i64,
+    choices: BTreeMap<usize, ChoiceState>,
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Default)]
+struct ChoiceState {
+    role: Option<String>,
+    content: Option<String>,
+    reasoning_content: Option<String>,
+    finish_reason: Option<String>,
+    tool_calls: BTreeMap<usize, ToolCallState>,
+    logprobs: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Debug, Default)]
+struct ToolCallState {
+    id: String,
+    r#type: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+impl StreamAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 合并一个数据块
+    pub fn push(&mut self, chunk: &ChatCompletionChunkResponse) {
+        if self.id.is_empty() {
+            self.id = chunk.id.clone();
+            self.model = chunk.model.clone();
+            self.created = chunk.created;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage.clone();
+        }
+        for stream_choice in &chunk.choices {
+            let choice = self.choices.entry(stream_choice.index).or_default();
+            if let Some(finish_reason) = &stream_choice.finish_reason {
+                choice.finish_reason = Some(finish_reason.clone());
+            }
+            if let Some(logprobs) = &stream_choice.logprobs {
+                choice
+                    .logprobs
+                    .get_or_insert_with(Vec::new)
+                    .extend(logprobs.content.iter().cloned());
+            }
+            let Some(delta) = &stream_choice.delta else {
+                continue;
+            };
+            if let Some(role) = &delta.role {
+                choice.role = Some(role.clone());
+            }
+            if let Some(content) = &delta.content {
+                choice.content.get_or_insert_with(String::new).push_str(content);
+            }
+            if let Some(reasoning) = &delta.reasoning_content {
+                choice.reasoning_content.get_or_insert_with(String::new).push_str(reasoning);
+            }
+            for delta_call in delta.tool_calls.iter().flatten() {
+                let call = choice.tool_calls.entry(delta_call.index).or_default();
+                if let Some(id) = &delta_call.id {
+                    call.id = id.clone();
+                }
+                if let Some(r#type) = &delta_call.r#type {
+                    call.r#type = Some(r#type.clone());
+                }
+                if let Some(function) = &delta_call.function {
+                    if let Some(name) = &function.name {
+                        call.name.push_str(name);
+                    }
+                    if let Some(arguments) = &function.arguments {
+                        call.arguments.push_str(arguments);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 目前为止拼接出的完整响应，object 固定为 chat.completion
+    pub fn finish(self) -> ChatCompletionResponse {
+        let choices = self
+            .choices
+            .into_iter()
+            .map(|(index, choice)| {
+                let tool_calls: Vec<MessageToolCall> = choice
+                    .tool_calls
+                    .into_values()
+                    .map(|call| MessageToolCall {
+                        id: call.id,
+                        r#type: call.r#type.unwrap_or_else(|| "function".to_string()),
+                        function: Function {
+                            name: call.name,
+                            arguments: call.arguments,
+                        },
+                    })
+                    .collect();
+                Choice {
+                    index,
+                    finish_reason: choice.finish_reason.unwrap_or_default(),
+                    message: Message {
+                        role: choice.role.unwrap_or_else(|| "assistant".to_string()),
+                        content: choice.content,
+                        reasoning_content: choice.reasoning_content,
+                        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    },
+                    logprobs: choice.logprobs.map(|content| ChoiceLogprobs { content }),
+                }
+            })
+            .collect();
+        ChatCompletionResponse {
+            id: self.id,
+            model: self.model,
+            object: "chat.completion".to_string(),
+            created: self.created,
+            choices,
+            usage: self.usage,
+        }
+    }
+
+    /// 消费整个流并合并，流中出错时直接返回错误
+    pub async fn collect(
+        stream: impl Stream<Item = Result<ChatCompletionChunkResponse>>,
+    ) -> Result<ChatCompletionResponse> {
+        let mut stream = pin!(stream);
+        let mut accumulator = Self::new();
+        while let Some(chunk) = stream.next().await {
+            accumulator.push(&chunk?);
+        }
+        Ok(accumulator.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    fn chunk(json: &str) -> ChatCompletionChunkResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn tool_call_deltas_should_be_merged() {
+        let chunks = [
+            r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{"index":0,"delta":{"role":"assistant","content":"","tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":""}}]}}]}"#,
+            r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"location\":"}}]}}]}"#,
+            r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":" \"北京\"}"}},{"index":1,"id":"call_2","type":"function","function":{"name":"get_time","arguments":"{}"}}]}}]}"#,
+            r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion.chunk","created":1718067849,"choices":[],"usage":{"prompt_tokens":80,"completion_tokens":20,"total_tokens":100}}"#,
+        ];
+        let mut accumulator = StreamAccumulator::new();
+        for json in chunks {
+            accumulator.push(&chunk(json));
+        }
+        let res = accumulator.finish();
+        assert_eq!(res.id, "0217");
+        assert_eq!(res.object, "chat.completion");
+        assert_eq!(res.usage.as_ref().unwrap().total_tokens, 100);
+        let choice = &res.choices[0];
+        assert_eq!(choice.finish_reason, "tool_calls");
+        assert_eq!(choice.message.role, "assistant");
+        let calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "get_current_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"location": "北京"}"#);
+        assert_eq!(calls[1].function.name, "get_time");
+    }
+
+    #[tokio::test]
+    async fn multiple_choices_should_be_merged_by_index() {
+        let chunks = vec![
+            Ok(chunk(r#"{"id":"0217","model":"m","object":"chat.completion.chunk","created":1,"choices":[{"index":1,"delta":{"role":"assistant","content":"你"}},{"index":0,"delta":{"role":"assistant","content":"我"}}]}"#)),
+            Ok(chunk(r#"{"id":"0217","model":"m","object":"chat.completion.chunk","created":1,"choices":[{"index":0,"delta":{"content":"是豆包"},"finish_reason":"stop"},{"index":1,"delta":{"content":"好"},"finish_reason":"length"}]}"#)),
+        ];
+        let res = StreamAccumulator::collect(stream::iter(chunks)).await.unwrap();
+        assert_eq!(res.choices.len(), 2);
+        assert_eq!(res.choices[0].message.content.as_deref(), Some("我是豆包"));
+        assert_eq!(res.choices[0].finish_reason, "stop");
+        assert_eq!(res.choices[1].message.content.as_deref(), Some("你好"));
+        assert_eq!(res.choices[1].finish_reason, "length");
+        assert!(res.choices[0].message.tool_calls.is_none());
+        assert!(res.usage.is_none());
+    }
+}
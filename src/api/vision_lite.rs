@@ -2,7 +2,10 @@ use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Clone, Debug, Builder)]
+use super::chat_completion::{DEFAULT_MAX_TOKENS, TOKENS_PER_MESSAGE};
+use crate::limiter::{estimate_tokens, ESTIMATED_IMAGE_TOKENS};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct VisionLiteRequest {
     /// 以 endpoint_id 索引对应的模型接入点
     model: String,
@@ -60,8 +63,20 @@ pub struct VisionLiteRequest {
     logit_bias: Option<HashMap<String, i32>>,
 }
 
+impl VisionLiteRequest {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// 预估本次请求消耗的 token 数（输入 + 最大输出），用于客户端限流，每张图片按固定值估算
+    pub fn estimate_tokens(&self) -> u32 {
+        let prompt: u32 = self.messages.iter().map(VisionLiteMessage::estimate_tokens).sum();
+        prompt + self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) as u32
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case", tag = "role")]
 pub enum VisionLiteMessage {
     /// System Message 系统消息
@@ -72,13 +87,25 @@ pub enum VisionLiteMessage {
     Assistant(AssistantMessage),
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl VisionLiteMessage {
+    /// 预估这条消息占用的输入 token 数，包含每条消息的格式开销
+    pub fn estimate_tokens(&self) -> u32 {
+        let tokens = match self {
+            VisionLiteMessage::System(m) => estimate_tokens(&m.content),
+            VisionLiteMessage::User(m) => m.content.iter().map(Content::estimate_tokens).sum(),
+            VisionLiteMessage::Assistant(m) => estimate_tokens(&m.content),
+        };
+        tokens + TOKENS_PER_MESSAGE
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SystemMessage {
     /// 消息内容
     content: String,
 }
 
-#[derive(Serialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ContentType {
     #[default]
@@ -86,12 +113,12 @@ pub enum ContentType {
     ImageUrl,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ImageUrlType {
     pub url: String,
 }
 
-#[derive(Serialize, Clone, Debug, Builder, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder, Default)]
 pub struct Content {
     pub r#type: ContentType,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -100,19 +127,29 @@ pub struct Content {
     pub image_url: Option<ImageUrlType>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl Content {
+    /// 预估占用的输入 token 数，图片按 ESTIMATED_IMAGE_TOKENS 计
+    pub fn estimate_tokens(&self) -> u32 {
+        match self.r#type {
+            ContentType::Text => self.text.as_deref().map_or(0, estimate_tokens),
+            ContentType::ImageUrl => ESTIMATED_IMAGE_TOKENS,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserMessage {
     /// 消息内容
     pub content: Vec<Content>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AssistantMessage {
     /// 消息内容
     content: String,
 }
 
-#[derive(Serialize, Clone, Debug, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder)]
 pub struct StreamOptionsParam {
     /// 是否包含本次请求的 token 用量统计信息
     /// false：不返回 token 用量信息
@@ -124,7 +161,7 @@ pub struct StreamOptionsParam {
 
 ////////////////////////////  Response  //////////////////////
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VisionLiteResponse {
     /// 本次请求的唯一标识
     pub id: String,
@@ -141,7 +178,7 @@ pub struct VisionLiteResponse {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Choice {
     /// 当前元素在 choices 列表的索引
     pub index: usize,
@@ -158,7 +195,7 @@ pub struct Choice {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     /// 固定为 assistant
     pub role: String,
@@ -167,45 +204,45 @@ pub struct Message {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceLogprobs {
     /// message列表中每个 content 元素中的 token 对数概率信息
-    content: Vec<TokenLogprob>,
+    pub content: Vec<TokenLogprob>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenLogprob {
     /// 当前 token
-    token: String,
+    pub token: String,
     /// 当前 token 的 UTF-8 值，格式为整数列表。当一个字符由多个 token 组成（表情符号或特殊字符等）时可以用于字符的编码和解码。如果 token 没有 UTF-8 值则为空。
-    bytes: Vec<usize>,
+    pub bytes: Vec<usize>,
     /// 当前 token 的对数概率
-    logprob: f32,
+    pub logprob: f32,
     /// 在当前 token 位置最有可能的标记及其对数概率的列表。在一些情况下，返回的数量可能比请求参数 top_logprobs 指定的数量要少。
-    top_logprobs: Option<Vec<TopLogprob>>,
+    pub top_logprobs: Option<Vec<TopLogprob>>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TopLogprob {
     /// 当前 token
-    token: String,
+    pub token: String,
     /// 当前 token 的 UTF-8 值，格式为整数列表。当一个字符由多个 token 组成（表情符号或特殊字符等）时可以用于字符的编码和解码。如果 token 没有 UTF-8 值则为空。
-    bytes: Vec<usize>,
+    pub bytes: Vec<usize>,
     /// 当前 token 的对数概率
-    logprob: f32,
+    pub logprob: f32,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     /// 输入的 prompt token 数量
-    prompt_tokens: usize,
+    pub prompt_tokens: usize,
     /// 模型生成的 token 数量
-    completion_tokens: usize,
+    pub completion_tokens: usize,
     /// 本次请求消耗的总 token 数量（输入 + 输出）
-    total_tokens: usize,
+    pub total_tokens: usize,
 }
 
 #[cfg(test)]
@@ -241,6 +278,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn vision_lite_should_round_trip() {
+        let request = r#"{"model":"ep-20240821165029-dcqm2","messages":[{"role":"system","content":"你好"},{"role":"user","content":[{"type":"text","text":"图片里有什么？"},{"type":"image_url","image_url":{"url":"https://example.com/cat.png"}}]},{"role":"assistant","content":"一只猫"}],"max_tokens":100}"#;
+        let json = serde_json::to_string(&serde_json::from_str::<VisionLiteRequest>(request).unwrap()).unwrap();
+        assert_eq!(json, request);
+
+        let response = r#"{"id":"0217","model":"doubao-vision-lite-32k-241015","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"一只猫"},"logprobs":null}],"usage":{"prompt_tokens":120,"completion_tokens":3,"total_tokens":123}}"#;
+        let json = serde_json::to_string(&serde_json::from_str::<VisionLiteResponse>(response).unwrap()).unwrap();
+        assert_eq!(json, response);
+    }
+
     #[tokio::test]
     async fn simple_lite_vision_should_work() -> Result<()> {
         let req = VisionLiteRequestBuilder::default()
@@ -0,0 +1,237 @@
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::api::embeddings::{EmbeddingsRequestBuilder, EmbeddingsResponse};
+use crate::error::{LlmError, Result};
+use crate::limiter::estimate_tokens;
+use crate::LlmSdk;
+
+/// 单次 embeddings 请求最多的文本数量
+pub const MAX_BATCH_INPUTS: usize = 256;
+/// 单条文本的最大 token 数
+pub const MAX_INPUT_TOKENS: u32 = 4096;
+/// 单条文本 utf-8 编码后的最大字节数
+pub const MAX_INPUT_BYTES: usize = 100_000;
+/// embed_all 默认同时进行中的批次数
+pub const DEFAULT_EMBED_CONCURRENCY: usize = 4;
+
+/// 按 EmbeddingsRequest::input 的限制检查输入：不能为空列表，每条文本不能为空，
+/// 且不超过 MAX_INPUT_TOKENS 个 token（按 `limiter::estimate_tokens` 估算）和 MAX_INPUT_BYTES 字节
+pub fn validate_inputs(inputs: &[String]) -> Result<()> {
+    if inputs.is_empty() {
+        return Err(LlmError::InvalidInput("input list is empty".to_string()));
+    }
+    for (index, text) in inputs.iter().enumerate() {
+        if text.is_empty() {
+            return Err(LlmError::InvalidInput(format!("input {} is empty", index)));
+        }
+        if text.len() > MAX_INPUT_BYTES {
+            return Err(LlmError::InvalidInput(format!(
+                "input {} is {} bytes, exceeds {} bytes",
+                index,
+                text.len(),
+                MAX_INPUT_BYTES
+            )));
+        }
+        let tokens = estimate_tokens(text);
+        if tokens > MAX_INPUT_TOKENS {
+            return Err(LlmError::InvalidInput(format!(
+                "input {} is about {} tokens, exceeds {} tokens",
+                index, tokens, MAX_INPUT_TOKENS
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl LlmSdk {
+    /// 向量化任意数量的文本，同时最多 DEFAULT_EMBED_CONCURRENCY 个批次，见 embed_all_with_concurrency
+    pub async fn embed_all(&self, model: impl Into<String>, inputs: Vec<String>) -> Result<EmbeddingsResponse> {
+        self.embed_all_with_concurrency(model, inputs, DEFAULT_EMBED_CONCURRENCY)
+            .await
+    }
+
+    /// 校验输入后按 MAX_BATCH_INPUTS 条一批拆分，最多 concurrency 个批次同时请求。
+    /// 每个批次失败时按 RetryPolicy 重试，仍然失败则整体返回错误。
+    /// 返回合并后的响应：data 的 index 对应 inputs 中的位置并按顺序排列，usage 为各批次之和，
+    /// id、model 和 created 取自第一个批次
+    pub async fn embed_all_with_concurrency(
+        &self,
+        model: impl Into<String>,
+        inputs: Vec<String>,
+        concurrency: usize,
+    ) -> Result<EmbeddingsResponse> {
+        validate_inputs(&inputs)?;
+        let model = model.into();
+        let mut inputs = inputs.into_iter();
+        let mut batches = Vec::new();
+        loop {
+            let batch: Vec<String> = inputs.by_ref().take(MAX_BATCH_INPUTS).collect();
+            if batch.is_empty() {
+                break;
+            }
+            batches.push(batch);
+        }
+
+        let responses: Vec<(usize, usize, EmbeddingsResponse)> = stream::iter(batches.into_iter().enumerate())
+            .map(|(i, batch)| {
+                let len = batch.len();
+                let request = EmbeddingsRequestBuilder::default()
+                    .model(model.clone())
+                    .input(batch)
+                    .build()
+                    .map_err(|e| LlmError::Config(e.to_string()));
+                async move {
+                    let res = self.embeddings(&request?).await?;
+                    Ok::<_, LlmError>((i * MAX_BATCH_INPUTS, len, res))
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await?;
+        merge(responses)
+    }
+}
+
+/// 按批次的起始位置合并响应，把每条 embedding 的 index 换算成在全部输入中的位置。
+/// responses 的元素为 (起始位置, 批次的输入数量, 响应)，
+/// 任一批次返回的向量和输入对不上时返回错误，避免结果错位
+fn merge(mut responses: Vec<(usize, usize, EmbeddingsResponse)>) -> Result<EmbeddingsResponse> {
+    for (offset, len, res) in &responses {
+        check_batch(*offset, *len, res)?;
+    }
+    responses.sort_by_key(|(offset, _, _)| *offset);
+    let mut responses = responses.into_iter();
+    let (_, _, mut merged) = responses.next().expect("inputs are validated to be non-empty");
+    for (offset, _, res) in responses {
+        merged.usage.prompt_tokens += res.usage.prompt_tokens;
+        merged.usage.total_tokens += res.usage.total_tokens;
+        merged.data.extend(res.data.into_iter().map(|mut embedding| {
+            embedding.index += offset;
+            embedding
+        }));
+    }
+    merged.data.sort_by_key(|embedding| embedding.index);
+    Ok(merged)
+}
+
+/// 每条输入都要有且只有一个向量
+fn check_batch(offset: usize, len: usize, res: &EmbeddingsResponse) -> Result<()> {
+    if res.data.len() != len {
+        return Err(LlmError::InvalidResponse(format!(
+            "batch at {} sent {} inputs but got {} embeddings",
+            offset,
+            len,
+            res.data.len()
+        )));
+    }
+    let mut seen = vec![false; len];
+    for embedding in &res.data {
+        match seen.get_mut(embedding.index) {
+            Some(seen) if !*seen => *seen = true,
+            _ => {
+                return Err(LlmError::InvalidResponse(format!(
+                    "batch at {} returned invalid or duplicate embedding index {}",
+                    offset, embedding.index
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_response, mock_sdk, mock_server};
+
+    /// 一个批次的响应，embedding 为 [offset + index]，data 倒序返回
+    fn batch_response(offset: usize, len: usize) -> String {
+        let data: Vec<String> = (0..len)
+            .rev()
+            .map(|i| format!(r#"{{"index":{},"embedding":[{}.0],"object":"embedding"}}"#, i, offset + i))
+            .collect();
+        let body = format!(
+            r#"{{"id":"0217{}","model":"doubao-embedding-text-240715","created":1729671495,"object":"list","data":[{}],"usage":{{"prompt_tokens":{},"total_tokens":{}}}}}"#,
+            offset,
+            data.join(","),
+            len,
+            len
+        );
+        http_response(200, &[], &body)
+    }
+
+    #[test]
+    fn inputs_should_be_validated() {
+        let err = validate_inputs(&[]).unwrap_err();
+        assert!(matches!(err, LlmError::InvalidInput(_)));
+        let err = validate_inputs(&["天很蓝".to_string(), String::new()]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid input: input 1 is empty");
+        let err = validate_inputs(&["a".repeat(MAX_INPUT_BYTES + 1)]).unwrap_err();
+        assert!(err.to_string().contains("exceeds 100000 bytes"));
+        let err = validate_inputs(&["蓝".repeat(MAX_INPUT_TOKENS as usize + 1)]).unwrap_err();
+        assert!(err.to_string().contains("exceeds 4096 tokens"));
+        assert_eq!(err.kind(), "invalid_input");
+        assert!(validate_inputs(&["天很蓝".to_string(), "海很深".to_string()]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn embed_all_should_batch_retry_and_reorder() {
+        let (base_url, requests) = mock_server(vec![
+            http_response(500, &[], r#"{"error":{"code":"InternalServiceError","message":"busy"}}"#),
+            batch_response(0, MAX_BATCH_INPUTS),
+            batch_response(MAX_BATCH_INPUTS, 44),
+        ])
+        .await;
+        let inputs: Vec<String> = (0..300).map(|i| format!("第 {} 条", i)).collect();
+        let res = mock_sdk(&base_url)
+            .embed_all_with_concurrency("ep-20241023154013-pzht4", inputs, 1)
+            .await
+            .unwrap();
+
+        assert_eq!(res.id, "02170");
+        assert_eq!(res.data.len(), 300);
+        for (i, embedding) in res.data.iter().enumerate() {
+            assert_eq!(embedding.index, i);
+            assert_eq!(embedding.embedding, vec![i as f32]);
+        }
+        assert_eq!(res.usage.prompt_tokens, 300);
+        assert_eq!(res.usage.total_tokens, 300);
+
+        // 第一个批次失败后重试了一次
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1].contains("第 255 条") && !requests[1].contains("第 256 条"));
+        assert!(requests[2].contains("第 256 条") && requests[2].contains("第 299 条"));
+    }
+
+    #[tokio::test]
+    async fn embed_all_should_reject_short_batches() {
+        let (base_url, _) = mock_server(vec![batch_response(0, MAX_BATCH_INPUTS), batch_response(MAX_BATCH_INPUTS, 43)]).await;
+        let inputs: Vec<String> = (0..300).map(|i| format!("第 {} 条", i)).collect();
+        let err = mock_sdk(&base_url)
+            .embed_all_with_concurrency("ep-20241023154013-pzht4", inputs, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LlmError::InvalidResponse(ref msg) if msg.contains("sent 44 inputs but got 43")), "{:?}", err);
+
+        // 数量对但 index 超出批次范围，替换前后长度相同，不影响 content-length
+        let body = batch_response(0, 2).replace(r#""index":1"#, r#""index":2"#);
+        let (base_url, _) = mock_server(vec![body]).await;
+        let err = mock_sdk(&base_url)
+            .embed_all("ep-20241023154013-pzht4", vec!["天很蓝".to_string(), "海很深".to_string()])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LlmError::InvalidResponse(ref msg) if msg.contains("index 2")), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn embed_all_should_fail_fast_on_invalid_input() {
+        let (base_url, requests) = mock_server(vec![batch_response(0, 1)]).await;
+        let err = mock_sdk(&base_url)
+            .embed_all("ep-20241023154013-pzht4", vec![])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LlmError::InvalidInput(_)));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
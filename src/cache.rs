@@ -0,0 +1,372 @@
+//! embeddings 缓存。给 LlmSdk 设置 embedding_cache 后，embeddings（以及基于它的 embed_all）
+//! 只把缓存中没有的文本发给方舟，结果按输入顺序和缓存命中的向量合并。
+//! 缓存键为 (model, encoding_format, sha256(text))。
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::api::embeddings::{Embedding, EmbeddingsRequest, EmbeddingsResponse, EncodingFormat, Usage};
+use crate::error::{LlmError, Result};
+use crate::meta::{RequestOptions, ResponseMeta, WithMeta};
+use crate::LlmSdk;
+
+/// 缓存键
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// 推理接入点 ID
+    pub model: String,
+    /// 请求的 encoding_format，不设置时按服务端默认的 float
+    pub encoding: EncodingFormat,
+    /// 文本的 sha256
+    pub text_sha256: [u8; 32],
+}
+
+impl CacheKey {
+    pub fn new(model: impl Into<String>, encoding: Option<EncodingFormat>, text: &str) -> Self {
+        Self {
+            model: model.into(),
+            encoding: encoding.unwrap_or(EncodingFormat::Float),
+            text_sha256: Sha256::digest(text.as_bytes()).into(),
+        }
+    }
+
+    /// 整个键的 sha256 十六进制字符串，可以用作文件名
+    pub fn digest(&self) -> String {
+        let encoding = match self.encoding {
+            EncodingFormat::Float => "float",
+            EncodingFormat::Base64 => "base64",
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(self.model.as_bytes());
+        hasher.update([0]);
+        hasher.update(encoding.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.text_sha256);
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// 缓存后端。缓存只是优化，读写失败时按未命中处理，不影响请求
+pub trait EmbeddingCache: fmt::Debug + Send + Sync {
+    fn get(&self, key: &CacheKey) -> Option<Vec<f32>>;
+    fn put(&self, key: CacheKey, embedding: Vec<f32>);
+}
+
+/// 内存中的 LRU 缓存，最多保存 capacity 条向量
+#[derive(Debug)]
+pub struct MemoryCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    /// 每次访问递增，值越小越久没有被访问
+    tick: u64,
+    entries: HashMap<CacheKey, (Vec<f32>, u64)>,
+    order: BTreeMap<u64, CacheKey>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl LruState {
+    fn touch(&mut self, key: &CacheKey) -> Option<&Vec<f32>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (embedding, last) = self.entries.get_mut(key)?;
+        self.order.remove(last);
+        self.order.insert(tick, key.clone());
+        *last = tick;
+        Some(embedding)
+    }
+}
+
+impl EmbeddingCache for MemoryCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<f32>> {
+        self.state.lock().unwrap().touch(key).cloned()
+    }
+
+    fn put(&self, key: CacheKey, embedding: Vec<f32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        if let Some((_, last)) = state.entries.insert(key.clone(), (embedding, tick)) {
+            state.order.remove(&last);
+        }
+        state.order.insert(tick, key);
+        while state.entries.len() > self.capacity {
+            let Some((_, oldest)) = state.order.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+    }
+}
+
+/// 本地文件缓存，每条向量保存为 dir/<digest 前两位>/<digest>，内容为小端序 f32 数组。
+/// 写入时先写临时文件再重命名，多个进程共用同一个目录也不会读到写了一半的文件
+#[derive(Debug, Clone)]
+pub struct FileCache {
+    dir: PathBuf,
+}
+
+impl FileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let digest = key.digest();
+        self.dir.join(&digest[..2]).join(digest)
+    }
+
+    fn write(&self, key: &CacheKey, embedding: &[f32]) -> io::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let bytes: Vec<u8> = embedding.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let tmp = path.with_extension(format!("tmp.{}.{}", std::process::id(), fastrand::u64(..)));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+}
+
+impl EmbeddingCache for FileCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<f32>> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        if bytes.is_empty() || bytes.len() % 4 != 0 {
+            warn!("ignore corrupted embedding cache file for {}", key.digest());
+            return None;
+        }
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect(),
+        )
+    }
+
+    fn put(&self, key: CacheKey, embedding: Vec<f32>) {
+        if let Err(e) = self.write(&key, &embedding) {
+            warn!("failed to write embedding cache: {}", e);
+        }
+    }
+}
+
+impl LlmSdk {
+    /// 先查缓存，只请求未命中的文本，再按输入顺序合并。
+    /// 全部命中时不发请求，返回的 id 为空、usage 为 0，meta 为默认值
+    pub(crate) async fn cached_embeddings(
+        &self,
+        cache: &dyn EmbeddingCache,
+        req: &EmbeddingsRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<EmbeddingsResponse>> {
+        let keys: Vec<CacheKey> = req
+            .input()
+            .iter()
+            .map(|text| CacheKey::new(req.model(), req.encoding_format(), text))
+            .collect();
+        let mut embeddings: Vec<Option<Vec<f32>>> = keys.iter().map(|key| cache.get(key)).collect();
+        let misses: Vec<usize> = (0..keys.len()).filter(|&i| embeddings[i].is_none()).collect();
+        debug!(
+            "embedding cache: {} hits, {} misses",
+            keys.len() - misses.len(),
+            misses.len()
+        );
+
+        let mut res = if misses.is_empty() {
+            WithMeta {
+                data: EmbeddingsResponse {
+                    id: String::new(),
+                    model: req.model().to_string(),
+                    created: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs() as usize),
+                    object: "list".to_string(),
+                    data: Vec::new(),
+                    usage: Usage {
+                        prompt_tokens: 0,
+                        total_tokens: 0,
+                    },
+                },
+                meta: ResponseMeta::default(),
+            }
+        } else {
+            let mut miss_req = req.clone();
+            miss_req.input = misses.iter().map(|&i| req.input()[i].clone()).collect();
+            self.send_embeddings(&miss_req, options).await?
+        };
+
+        for embedding in std::mem::take(&mut res.data.data) {
+            let Some(&i) = misses.get(embedding.index) else {
+                return Err(LlmError::InvalidResponse(format!(
+                    "embedding index {} is out of range, {} inputs were sent",
+                    embedding.index,
+                    misses.len()
+                )));
+            };
+            embeddings[i] = Some(embedding.embedding);
+        }
+        // 缺了任何一条都会让结果和 input 错位，整体返回错误
+        let embeddings = embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| {
+                embedding.ok_or_else(|| LlmError::InvalidResponse(format!("no embedding returned for input {}", i)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for &i in &misses {
+            cache.put(keys[i].clone(), embeddings[i].clone());
+        }
+        res.data.data = embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| Embedding {
+                index,
+                embedding,
+                object: "embedding".to_string(),
+            })
+            .collect();
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::embeddings::EmbeddingsRequestBuilder;
+    use crate::tests::{http_response, mock_sdk, mock_server};
+    use std::sync::Arc;
+
+    fn key(text: &str) -> CacheKey {
+        CacheKey::new("ep-20241023154013-pzht4", Some(EncodingFormat::Base64), text)
+    }
+
+    fn request(input: &[&str]) -> EmbeddingsRequest {
+        EmbeddingsRequestBuilder::default()
+            .model("ep-20241023154013-pzht4".to_string())
+            .input(input.iter().map(|text| text.to_string()).collect::<Vec<_>>())
+            .build()
+            .unwrap()
+    }
+
+    fn response(embeddings: &[f32]) -> String {
+        let data: Vec<String> = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!(r#"{{"index":{},"embedding":[{:?}, 1.0],"object":"embedding"}}"#, i, v))
+            .collect();
+        let body = format!(
+            r#"{{"id":"0217","model":"doubao-embedding-text-240715","created":1729671495,"object":"list","data":[{}],"usage":{{"prompt_tokens":{},"total_tokens":{}}}}}"#,
+            data.join(","),
+            embeddings.len(),
+            embeddings.len()
+        );
+        http_response(200, &[], &body)
+    }
+
+    #[test]
+    fn memory_cache_should_evict_least_recently_used() {
+        let cache = MemoryCache::new(2);
+        cache.put(key("a"), vec![1.0]);
+        cache.put(key("b"), vec![2.0]);
+        assert_eq!(cache.get(&key("a")), Some(vec![1.0]));
+        cache.put(key("c"), vec![3.0]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!(cache.get(&key("a")), Some(vec![1.0]));
+        assert_eq!(cache.get(&key("c")), Some(vec![3.0]));
+
+        // model 和 encoding 都是键的一部分
+        assert_ne!(key("a"), CacheKey::new("ep-2", Some(EncodingFormat::Base64), "a"));
+        assert_ne!(key("a").digest(), CacheKey::new("ep-20241023154013-pzht4", None, "a").digest());
+    }
+
+    #[test]
+    fn file_cache_should_persist() {
+        let dir = std::env::temp_dir().join(format!("llm-sdk-cache-{}", std::process::id()));
+        let cache = FileCache::new(&dir).unwrap();
+        assert_eq!(cache.get(&key("天很蓝")), None);
+        cache.put(key("天很蓝"), vec![0.5, -1.25]);
+
+        let reopened = FileCache::new(&dir).unwrap();
+        assert_eq!(reopened.get(&key("天很蓝")), Some(vec![0.5, -1.25]));
+        assert_eq!(reopened.get(&key("海很深")), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn embeddings_should_only_send_cache_misses() {
+        let (base_url, requests) = mock_server(vec![response(&[1.0, 2.0]), response(&[3.0])]).await;
+        let mut sdk = mock_sdk(&base_url);
+        let cache = Arc::new(MemoryCache::new(100));
+        sdk.embedding_cache = Some(cache.clone());
+
+        sdk.embeddings(&request(&["天很蓝", "海很深"])).await.unwrap();
+        assert_eq!(cache.len(), 2);
+
+        let res = sdk.embeddings(&request(&["海很深", "山很高", "天很蓝"])).await.unwrap();
+        let firsts: Vec<f32> = res.vectors().iter().map(|v| v[0]).collect();
+        assert_eq!(firsts, [2.0, 3.0, 1.0]);
+        assert_eq!(res.data.iter().map(|e| e.index).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(res.usage.total_tokens, 1);
+
+        // 全部命中时不再发请求
+        let res = sdk.embeddings(&request(&["山很高"])).await.unwrap();
+        assert_eq!(res.vectors()[0][0], 3.0);
+        assert_eq!(res.usage.total_tokens, 0);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains(r#""input":["山很高"]"#));
+    }
+
+    #[tokio::test]
+    async fn incomplete_response_should_fail() {
+        let out_of_range = response(&[1.0, 2.0]).replace(r#""index":1"#, r#""index":5"#);
+        let (base_url, _) = mock_server(vec![response(&[1.0]), out_of_range]).await;
+        let mut sdk = mock_sdk(&base_url);
+        let cache = Arc::new(MemoryCache::new(100));
+        sdk.embedding_cache = Some(cache.clone());
+
+        // 发送了两条，只返回一条
+        let err = sdk.embeddings(&request(&["天很蓝", "海很深"])).await.unwrap_err();
+        assert!(matches!(err, LlmError::InvalidResponse(ref msg) if msg.contains("input 1")), "{:?}", err);
+        let err = sdk.embeddings(&request(&["天很蓝", "海很深"])).await.unwrap_err();
+        assert!(matches!(err, LlmError::InvalidResponse(ref msg) if msg.contains("index 5")), "{:?}", err);
+        assert_eq!(err.kind(), "invalid_response");
+        // 出错的响应不会写入缓存
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,156 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Proxy, Url};
+use std::time::Duration;
+
+use crate::error::{LlmError, Result};
+
+/// 默认请求超时时间（秒）
+pub const DEFAULT_TIMEOUT: u64 = 120;
+/// 默认建立连接超时时间（秒）
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/// HTTP 客户端配置，LlmSdk 构造时据此创建一个共享的 reqwest::Client
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// 建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 单次请求的超时时间，流式请求从发起到读完最后一个事件都算在内
+    pub timeout: Duration,
+    /// 每个 host 最多保留的空闲连接数，None 表示使用 reqwest 默认值
+    pub pool_max_idle_per_host: Option<usize>,
+    /// 空闲连接的保留时长，None 表示使用 reqwest 默认值
+    pub pool_idle_timeout: Option<Duration>,
+    /// 直接使用 HTTP/2 建立连接
+    pub http2_prior_knowledge: bool,
+    /// 代理地址，比如 http://127.0.0.1:7890
+    pub proxy: Option<String>,
+    /// User-Agent 请求头
+    pub user_agent: Option<String>,
+    /// 每个请求都会带上的请求头
+    pub default_headers: HeaderMap,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            http2_prior_knowledge: false,
+            proxy: None,
+            user_agent: None,
+            default_headers: HeaderMap::new(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .default_headers(self.default_headers.clone());
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy)
+                .map_err(|e| LlmError::Config(format!("invalid proxy {}: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        builder
+            .build()
+            .map_err(|e| LlmError::Config(format!("failed to build http client: {}", e)))
+    }
+}
+
+/// 方舟服务所在地域
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Region {
+    /// 华北 2（北京）
+    #[default]
+    CnBeijing,
+    /// 华东 2（上海）
+    CnShanghai,
+    /// 亚太东南（柔佛），BytePlus ModelArk
+    ApSoutheast,
+    /// 自定义地址，需包含 /api/v3 之类的路径前缀，比如 http://127.0.0.1:8080/api/v3
+    Custom(String),
+}
+
+impl Region {
+    pub fn base_url(&self) -> String {
+        match self {
+            Region::CnBeijing => "https://ark.cn-beijing.volces.com/api/v3".to_string(),
+            Region::CnShanghai => "https://ark.cn-shanghai.volces.com/api/v3".to_string(),
+            Region::ApSoutheast => "https://ark.ap-southeast.bytepluses.com/api/v3".to_string(),
+            Region::Custom(url) => url.clone(),
+        }
+    }
+}
+
+/// 校验 base_url，并保证路径以 / 结尾，这样 join 相对路径时不会丢掉 /api/v3
+pub fn parse_base_url(base_url: &str) -> Result<Url> {
+    let mut url = Url::parse(base_url)
+        .map_err(|e| LlmError::Config(format!("invalid base url {}: {}", base_url, e)))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(LlmError::Config(format!(
+            "base url must be an http(s) url with host: {}",
+            base_url
+        )));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(LlmError::Config(format!(
+            "base url must not contain query or fragment: {}",
+            base_url
+        )));
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+/// 把接口路径拼到 base_url 后面，比如 chat/completions
+pub fn join_url(base_url: &Url, path: &str) -> Result<Url> {
+    base_url
+        .join(path.trim_start_matches('/'))
+        .map_err(|e| LlmError::Config(format!("invalid endpoint path {}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_base_url_should_work() {
+        let url = parse_base_url(&Region::CnBeijing.base_url()).unwrap();
+        assert_eq!(
+            join_url(&url, "chat/completions").unwrap().as_str(),
+            "https://ark.cn-beijing.volces.com/api/v3/chat/completions"
+        );
+        let url = parse_base_url(&Region::Custom("http://127.0.0.1:8080/api/v3/".into()).base_url()).unwrap();
+        assert_eq!(
+            join_url(&url, "/embeddings").unwrap().as_str(),
+            "http://127.0.0.1:8080/api/v3/embeddings"
+        );
+    }
+
+    #[test]
+    fn invalid_base_url_should_be_rejected() {
+        assert!(parse_base_url("/api/v3/chat/completions").is_err());
+        assert!(parse_base_url("ftp://ark.cn-beijing.volces.com/api/v3").is_err());
+        assert!(parse_base_url("https://ark.cn-beijing.volces.com/api/v3?x=1").is_err());
+    }
+}
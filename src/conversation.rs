@@ -0,0 +1,246 @@
+use crate::api::chat_completion::{
+    ChatCompletionMessage, ChatCompletionRequest, ChatCompletionResponse, ToolMessage, UserMessage,
+};
+use crate::error::Result;
+use crate::tools::ToolRegistry;
+use crate::LlmSdk;
+
+/// 多轮对话，保存完整的消息历史，每次请求时自动带上。
+/// 设置了 token 预算时，从最早的一轮开始整轮丢弃，system 消息和最新的一轮始终保留。
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    /// 请求模板，除 messages 以外的参数（model、temperature、tools 等）都从这里来
+    template: ChatCompletionRequest,
+    messages: Vec<ChatCompletionMessage>,
+    /// 输入消息的 token 预算，不包含 tools 和输出
+    token_budget: Option<u32>,
+}
+
+impl Conversation {
+    /// 以 template 为请求模板创建对话，template 中已有的消息作为初始历史
+    pub fn new(template: ChatCompletionRequest) -> Self {
+        let messages = template.messages.clone();
+        Self {
+            template,
+            messages,
+            token_budget: None,
+        }
+    }
+
+    /// 设置输入消息的 token 预算，按 `limiter::estimate_tokens` 估算
+    pub fn with_token_budget(mut self, token_budget: u32) -> Self {
+        self.token_budget = Some(token_budget);
+        self
+    }
+
+    /// 完整的消息历史，不受 token 预算影响
+    pub fn messages(&self) -> &[ChatCompletionMessage] {
+        &self.messages
+    }
+
+    pub fn push(&mut self, message: ChatCompletionMessage) {
+        self.messages.push(message);
+    }
+
+    pub fn push_user(&mut self, content: impl Into<String>) {
+        self.push(ChatCompletionMessage::User(UserMessage {
+            content: content.into(),
+        }));
+    }
+
+    pub fn push_tool_result(&mut self, message: ToolMessage) {
+        self.push(ChatCompletionMessage::Tool(message));
+    }
+
+    /// 把模型的回复追加到历史中
+    pub fn record_response(&mut self, response: &ChatCompletionResponse) {
+        if let Some(choice) = response.choices.first() {
+            self.push(ChatCompletionMessage::Assistant((&choice.message).into()));
+        }
+    }
+
+    /// 清空历史，只保留 system 消息
+    pub fn clear(&mut self) {
+        self.messages.retain(|message| matches!(message, ChatCompletionMessage::System(_)));
+    }
+
+    /// 生成下一次请求，超出 token 预算时丢弃最早的几轮对话
+    pub fn request(&self) -> ChatCompletionRequest {
+        let mut request = self.template.clone();
+        request.messages = match self.token_budget {
+            Some(budget) => truncate(&self.messages, budget),
+            None => self.messages.clone(),
+        };
+        request
+    }
+
+    /// 发送一条用户消息，并把回复追加到历史中。请求失败时历史保持不变，可以直接重试
+    pub async fn send(&mut self, sdk: &LlmSdk, content: impl Into<String>) -> Result<ChatCompletionResponse> {
+        let len = self.messages.len();
+        self.push_user(content);
+        match sdk.chat_completion(&self.request()).await {
+            Ok(response) => {
+                self.record_response(&response);
+                Ok(response)
+            }
+            Err(e) => {
+                self.messages.truncate(len);
+                Err(e)
+            }
+        }
+    }
+
+    /// 发送一条用户消息，自动执行工具调用，中间的 Assistant Message 和 Tool Message 都会追加到历史中。
+    /// 请求失败时历史保持不变
+    pub async fn send_with_tools(
+        &mut self,
+        sdk: &LlmSdk,
+        content: impl Into<String>,
+        registry: &ToolRegistry,
+        max_iterations: usize,
+    ) -> Result<ChatCompletionResponse> {
+        let len = self.messages.len();
+        self.push_user(content);
+        let request = self.request();
+        let sent = request.messages.len();
+        let run = match sdk.run_with_tools(&request, registry, max_iterations).await {
+            Ok(run) => run,
+            Err(e) => {
+                self.messages.truncate(len);
+                return Err(e);
+            }
+        };
+        // run.messages 以发送的消息开头，后面是本轮新增的消息
+        self.messages.extend(run.messages.into_iter().skip(sent));
+        Ok(run.response)
+    }
+}
+
+/// 按轮次（以 User 消息开始）从前往后丢弃，保证 Tool 消息不会和对应的 tool_calls 分开
+fn truncate(messages: &[ChatCompletionMessage], budget: u32) -> Vec<ChatCompletionMessage> {
+    let (system, rest): (Vec<_>, Vec<_>) = messages
+        .iter()
+        .partition(|message| matches!(message, ChatCompletionMessage::System(_)));
+    let mut turns: Vec<Vec<&ChatCompletionMessage>> = Vec::new();
+    for message in rest {
+        match (message, turns.last_mut()) {
+            (ChatCompletionMessage::User(_), _) | (_, None) => turns.push(vec![message]),
+            (_, Some(turn)) => turn.push(message),
+        }
+    }
+    let tokens = |turn: &Vec<&ChatCompletionMessage>| -> u32 { turn.iter().map(|m| m.estimate_tokens()).sum() };
+    let mut used: u32 = system.iter().map(|m| m.estimate_tokens()).sum();
+    let mut keep = turns.len();
+    // 从最新的一轮往前累加，最新的一轮超出预算也保留
+    for (i, turn) in turns.iter().enumerate().rev() {
+        let turn_tokens = tokens(turn);
+        if keep < turns.len() && used + turn_tokens > budget {
+            break;
+        }
+        used += turn_tokens;
+        keep = i;
+    }
+    system
+        .into_iter()
+        .chain(turns[keep..].iter().flatten().copied())
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat_completion::{ChatCompletionRequestBuilder, SystemMessage};
+    use crate::tests::{http_response, mock_sdk, mock_server, CHAT_COMPLETION_RESPONSE};
+
+    fn conversation() -> Conversation {
+        Conversation::new(
+            ChatCompletionRequestBuilder::default()
+                .model("ep-20240817170913-w9q57".to_string())
+                .messages(vec![ChatCompletionMessage::System(SystemMessage {
+                    content: "你是我的私人助理，可以帮我记一些东西".to_string(),
+                })])
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn contents(messages: &[ChatCompletionMessage]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| match message {
+                ChatCompletionMessage::System(m) => m.content.clone(),
+                ChatCompletionMessage::User(m) => m.content.clone(),
+                ChatCompletionMessage::Assistant(m) => m.content.clone().unwrap_or_default(),
+                ChatCompletionMessage::Tool(m) => m.content.clone(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_should_keep_history() {
+        let (base_url, requests) = mock_server(vec![http_response(200, &[], CHAT_COMPLETION_RESPONSE)]).await;
+        let sdk = mock_sdk(&base_url);
+        let mut conversation = conversation();
+        conversation.send(&sdk, "帮我记一下，明天上午 9 点开会").await.unwrap();
+        conversation.send(&sdk, "明天有什么安排？").await.unwrap();
+        assert_eq!(conversation.messages().len(), 5);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[1].contains("明天上午 9 点开会"));
+        assert!(requests[1].contains(r#"{"role":"assistant","content":"你好，我是豆包""#));
+    }
+
+    #[tokio::test]
+    async fn failed_send_should_not_change_history() {
+        let body = r#"{"error":{"code":"InputTextSensitiveContentDetected","message":"sensitive"}}"#;
+        let (base_url, requests) = mock_server(vec![
+            http_response(400, &[], body),
+            http_response(400, &[], body),
+            http_response(200, &[], CHAT_COMPLETION_RESPONSE),
+        ])
+        .await;
+        let sdk = mock_sdk(&base_url);
+        let mut conversation = conversation();
+        let before = contents(conversation.messages());
+        assert!(conversation.send(&sdk, "帮我记一下，明天上午 9 点开会").await.is_err());
+        assert_eq!(contents(conversation.messages()), before);
+        let registry = ToolRegistry::new();
+        assert!(conversation.send_with_tools(&sdk, "帮我记一下，明天上午 9 点开会", &registry, 3).await.is_err());
+        assert_eq!(contents(conversation.messages()), before);
+
+        // 重试时只发送一次用户消息
+        conversation.send(&sdk, "帮我记一下，明天上午 9 点开会").await.unwrap();
+        assert_eq!(conversation.messages().len(), 3);
+        assert_eq!(requests.lock().unwrap()[2].matches("明天上午 9 点开会").count(), 1);
+    }
+
+    #[test]
+    fn token_budget_should_drop_oldest_turns() {
+        let mut conversation = conversation().with_token_budget(70);
+        for i in 0..5 {
+            conversation.push_user(format!("第 {} 轮的问题", i));
+            conversation.push(ChatCompletionMessage::Assistant(crate::api::chat_completion::AssistantMessage {
+                content: Some(format!("第 {} 轮的回答", i)),
+                tool_calls: None,
+            }));
+        }
+        let request = conversation.request();
+        let sent = contents(request.messages());
+        // system 始终保留，后面是最近的几轮，且每轮都是完整的
+        assert_eq!(sent[0], "你是我的私人助理，可以帮我记一些东西");
+        assert_eq!(sent.last().unwrap(), "第 4 轮的回答");
+        assert!(sent.len() < 11 && sent.len() % 2 == 1);
+        assert!(!sent.contains(&"第 0 轮的问题".to_string()));
+        let tokens: u32 = request.messages().iter().map(|m| m.estimate_tokens()).sum();
+        assert!(tokens <= 70);
+        assert_eq!(conversation.messages().len(), 11);
+
+        // 预算比最新一轮还小时也保留最新一轮
+        let request = conversation.clone().with_token_budget(1).request();
+        assert_eq!(contents(request.messages())[1..], ["第 4 轮的问题", "第 4 轮的回答"]);
+
+        conversation.clear();
+        assert_eq!(conversation.messages().len(), 1);
+    }
+}
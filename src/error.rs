@@ -0,0 +1,289 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::index::IndexError;
+use crate::meta;
+use crate::retry::RetryPolicy;
+
+pub type Result<T, E = LlmError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum LlmError {
+    /// 网络层错误，比如连接失败、连接被重置
+    #[error("transport error: {0}")]
+    Transport(#[source] reqwest::Error),
+    /// 请求超时
+    #[error("request timed out: {0}")]
+    Timeout(#[source] reqwest::Error),
+    /// 接口返回了非 2xx 状态码
+    #[error("API failed with status {status}: {error}")]
+    Api {
+        status: StatusCode,
+        /// 503 响应中 Retry-After 响应头给出的等待时间
+        retry_after: Option<Duration>,
+        error: Box<ArkError>,
+    },
+    /// 触发限流（429）
+    #[error("rate limited: {error}")]
+    RateLimited {
+        /// Retry-After 响应头给出的等待时间
+        retry_after: Option<Duration>,
+        error: Box<ArkError>,
+    },
+    /// 输入或输出命中内容审核
+    #[error("content filtered: {error}")]
+    ContentFilter { status: StatusCode, error: Box<ArkError> },
+    /// 响应体反序列化失败，body 为原始响应内容
+    #[error("failed to deserialize response: {source}")]
+    Deserialize {
+        #[source]
+        source: serde_json::Error,
+        body: String,
+    },
+    /// 模型输出的内容不能解析成期望的结构，text 为模型的原始输出
+    #[error("failed to parse model output: {source}")]
+    Output {
+        #[source]
+        source: serde_json::Error,
+        text: String,
+        /// 输出被截断（length）时通常是 max_tokens 不够
+        finish_reason: Option<String>,
+    },
+    /// 流式响应不符合 SSE 协议约定，或者流中返回了错误
+    #[error("stream protocol error: {0}")]
+    Stream(String),
+    /// SDK 配置错误，比如代理地址不合法
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// 请求参数不满足接口限制，没有发送请求
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    /// 响应可以解析，但内容和请求对不上，比如返回的向量数量少于输入
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// 向量索引出错
+    #[error(transparent)]
+    Index(#[from] IndexError),
+}
+
+impl LlmError {
+    /// 按默认的重试策略是否值得重试，见 `RetryPolicy::should_retry`
+    pub fn is_retryable(&self) -> bool {
+        RetryPolicy::default().should_retry(self)
+    }
+
+    /// 错误类型的简短名称，用作 tracing span 的 error.type 属性
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LlmError::Transport(_) => "transport",
+            LlmError::Timeout(_) => "timeout",
+            LlmError::Api { .. } => "api",
+            LlmError::RateLimited { .. } => "rate_limited",
+            LlmError::ContentFilter { .. } => "content_filter",
+            LlmError::Deserialize { .. } => "deserialize",
+            LlmError::Output { .. } => "output",
+            LlmError::Stream(_) => "stream",
+            LlmError::Config(_) => "config",
+            LlmError::InvalidInput(_) => "invalid_input",
+            LlmError::InvalidResponse(_) => "invalid_response",
+            LlmError::Index(_) => "index",
+        }
+    }
+
+    /// 接口返回的 HTTP 状态码
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            LlmError::Api { status, .. } | LlmError::ContentFilter { status, .. } => Some(*status),
+            LlmError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            LlmError::Transport(e) | LlmError::Timeout(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// 服务端通过 Retry-After 响应头要求的等待时间，只有 429 和 503 会携带
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LlmError::RateLimited { retry_after, .. } | LlmError::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// 方舟返回的错误详情
+    pub fn ark_error(&self) -> Option<&ArkError> {
+        match self {
+            LlmError::Api { error, .. }
+            | LlmError::RateLimited { error, .. }
+            | LlmError::ContentFilter { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// 方舟返回的请求 ID，排查问题时提供给方舟
+    pub fn request_id(&self) -> Option<&str> {
+        self.ark_error()?.request_id.as_deref()
+    }
+
+    /// 根据失败响应的状态码、响应头和响应体构造错误
+    pub(crate) fn from_http(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let mut err = Self::from_response(status, meta::retry_after(headers), body);
+        if let LlmError::Api { error, .. }
+        | LlmError::RateLimited { error, .. }
+        | LlmError::ContentFilter { error, .. } = &mut err
+        {
+            error.request_id = meta::header_str(headers, meta::REQUEST_ID_HEADER);
+        }
+        err
+    }
+
+    /// 根据失败响应的状态码和响应体构造错误
+    pub(crate) fn from_response(
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> Self {
+        let error = Box::new(ArkError::from_body(body));
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            LlmError::RateLimited { retry_after, error }
+        } else if error.is_content_filter() {
+            LlmError::ContentFilter { status, error }
+        } else {
+            // Retry-After 只对 429 和 503 有意义
+            let retry_after = retry_after.filter(|_| status == StatusCode::SERVICE_UNAVAILABLE);
+            LlmError::Api {
+                status,
+                retry_after,
+                error,
+            }
+        }
+    }
+
+    pub(crate) fn deserialize(source: serde_json::Error, body: impl Into<String>) -> Self {
+        LlmError::Deserialize {
+            source,
+            body: body.into(),
+        }
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LlmError::Timeout(e)
+        } else {
+            LlmError::Transport(e)
+        }
+    }
+}
+
+/// 方舟接口的错误信息
+/// https://www.volcengine.com/docs/82379/1299023
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ArkError {
+    /// 错误码，比如 InvalidParameter
+    #[serde(default)]
+    pub code: String,
+    /// 错误描述
+    #[serde(default)]
+    pub message: String,
+    /// 错误类型，比如 BadRequest
+    #[serde(default)]
+    pub r#type: Option<String>,
+    /// 出错的请求参数
+    #[serde(default)]
+    pub param: Option<String>,
+    /// 响应头里的 x-request-id，不在响应体中
+    #[serde(skip)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ArkErrorBody {
+    pub(crate) error: ArkError,
+}
+
+impl ArkError {
+    /// 解析 `{"error": {...}}` 格式的响应体，解析不了时把原始内容放进 message
+    pub(crate) fn from_body(body: &str) -> Self {
+        match serde_json::from_str::<ArkErrorBody>(body) {
+            Ok(body) => body.error,
+            Err(_) => ArkError {
+                message: body.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// 是否为内容审核拦截，比如 InputTextSensitiveContentDetected、OutputImageSensitiveContentDetected
+    pub fn is_content_filter(&self) -> bool {
+        self.code.contains("SensitiveContentDetected")
+    }
+}
+
+impl fmt::Display for ArkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.code.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.code, self.message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ark_error_should_parse() {
+        let body = r#"{"error":{"code":"InvalidParameter","message":"A parameter specified in the request is not valid","param":"messages","type":"BadRequest"}}"#;
+        let err = LlmError::from_response(StatusCode::BAD_REQUEST, None, body);
+        let ark = err.ark_error().unwrap();
+        assert_eq!(ark.code, "InvalidParameter");
+        assert_eq!(ark.param.as_deref(), Some("messages"));
+        assert_eq!(ark.r#type.as_deref(), Some("BadRequest"));
+        assert!(matches!(err, LlmError::Api { .. }));
+        assert!(!err.is_retryable());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(meta::REQUEST_ID_HEADER, "021729671495".parse().unwrap());
+        headers.insert(reqwest::header::RETRY_AFTER, "2".parse().unwrap());
+        let err = LlmError::from_http(StatusCode::TOO_MANY_REQUESTS, &headers, body);
+        assert_eq!(err.request_id(), Some("021729671495"));
+        assert!(matches!(err, LlmError::RateLimited { retry_after: Some(d), .. } if d.as_secs() == 2));
+    }
+
+    #[test]
+    fn error_kind_should_follow_status_and_code() {
+        let err = LlmError::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(3)),
+            r#"{"error":{"code":"RateLimitExceeded.EndpointRPMExceeded","message":"too many requests"}}"#,
+        );
+        assert!(matches!(err, LlmError::RateLimited { retry_after: Some(d), .. } if d.as_secs() == 3));
+        assert!(err.is_retryable());
+
+        let err = LlmError::from_response(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":{"code":"InputTextSensitiveContentDetected","message":"sensitive"}}"#,
+        );
+        assert!(matches!(err, LlmError::ContentFilter { .. }));
+
+        let err = LlmError::from_response(StatusCode::BAD_GATEWAY, None, "<html>bad gateway</html>");
+        assert_eq!(err.ark_error().unwrap().message, "<html>bad gateway</html>");
+        assert!(err.is_retryable());
+        // 和 RetryPolicy 的默认值一致：409 和 501 不重试，408 重试
+        assert!(!LlmError::from_response(StatusCode::CONFLICT, None, "").is_retryable());
+        assert!(!LlmError::from_response(StatusCode::NOT_IMPLEMENTED, None, "").is_retryable());
+        assert!(LlmError::from_response(StatusCode::REQUEST_TIMEOUT, None, "").is_retryable());
+
+        let err = LlmError::from_response(StatusCode::SERVICE_UNAVAILABLE, Some(Duration::from_secs(5)), "");
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(5)));
+        let err = LlmError::from_response(StatusCode::INTERNAL_SERVER_ERROR, Some(Duration::from_secs(5)), "");
+        assert_eq!(err.retry_after(), None);
+    }
+}
@@ -0,0 +1,701 @@
+//! 内存中的向量索引，保存 (id, text, vector, metadata)，支持精确 top-k 余弦检索和可选的 HNSW 近似检索，
+//! 可以保存成紧凑的二进制文件再加载回来。向量插入时归一化，检索时用点积代替余弦相似度。
+
+use derive_builder::Builder;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::api::embeddings::EmbeddingsResponse;
+use crate::vector::{dot, normalized, top_k};
+
+/// 索引文件的魔数和版本号
+const MAGIC: &[u8; 4] = b"ARKV";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("index io error: {0}")]
+    Io(#[from] io::Error),
+    /// 索引文件损坏或者不是本 SDK 生成的
+    #[error("invalid index file: {0}")]
+    InvalidFormat(String),
+    /// 向量维度和索引中已有的向量不一致
+    #[error("dimension mismatch: expected {expected}, got {actual}")]
+    DimensionMismatch { expected: usize, actual: usize },
+    /// 文档数量和 embeddings 响应中的向量数量对不上
+    #[error("no embedding for document {0}")]
+    MissingEmbedding(usize),
+    /// HNSW 参数不合法
+    #[error("invalid hnsw config: {0}")]
+    InvalidConfig(String),
+}
+
+/// 待向量化的文档
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub id: String,
+    pub text: String,
+    /// 任意附加信息，比如来源文件、页码
+    pub metadata: serde_json::Value,
+}
+
+impl Document {
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            text: text.into(),
+            metadata: serde_json::Value::Null,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// 索引中的一条记录，vector 为归一化后的向量
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub id: String,
+    pub text: String,
+    pub vector: Vec<f32>,
+    pub metadata: serde_json::Value,
+}
+
+/// 一条检索结果
+#[derive(Debug, Clone, Copy)]
+pub struct SearchHit<'a> {
+    pub entry: &'a IndexEntry,
+    /// 与查询向量的余弦相似度
+    pub score: f32,
+}
+
+/// HNSW 图的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct HnswConfig {
+    /// 每个节点在上层保留的邻居数，第 0 层保留 2 * m 个
+    #[builder(default = "16")]
+    pub m: usize,
+    /// 建图时每层搜索的候选数量，越大图质量越好、建图越慢
+    #[builder(default = "100")]
+    pub ef_construction: usize,
+    /// 检索时第 0 层的候选数量，至少为 k，越大召回率越高、检索越慢
+    #[builder(default = "50")]
+    pub ef_search: usize,
+    /// 随机层数的种子，相同的数据和种子建出相同的图
+    #[builder(default = "42")]
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfigBuilder::default().build().unwrap()
+    }
+}
+
+impl HnswConfig {
+    /// m 或 ef_construction 为 0 时图中没有边，检索会退化成只看入口节点
+    pub fn validate(&self) -> Result<(), IndexError> {
+        check_hnsw_config(self.m, self.ef_construction).map_err(IndexError::InvalidConfig)
+    }
+}
+
+impl HnswConfigBuilder {
+    fn validate(&self) -> Result<(), String> {
+        check_hnsw_config(self.m.unwrap_or(1), self.ef_construction.unwrap_or(1))
+    }
+}
+
+fn check_hnsw_config(m: usize, ef_construction: usize) -> Result<(), String> {
+    if m == 0 {
+        return Err("m must be at least 1".to_string());
+    }
+    if ef_construction == 0 {
+        return Err("ef_construction must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+/// 向量索引，默认精确检索，开启 HNSW 后 search 走近似检索
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddingIndex {
+    dims: usize,
+    entries: Vec<IndexEntry>,
+    hnsw: Option<Hnsw>,
+}
+
+impl EmbeddingIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 开启 HNSW 近似检索，已有的记录会重新建图
+    pub fn with_hnsw(mut self, config: HnswConfig) -> Result<Self, IndexError> {
+        config.validate()?;
+        let mut hnsw = Hnsw::new(config);
+        for node in 0..self.entries.len() {
+            hnsw.insert(&self.entries, node);
+        }
+        self.hnsw = Some(hnsw);
+        Ok(self)
+    }
+
+    pub fn hnsw_config(&self) -> Option<&HnswConfig> {
+        self.hnsw.as_ref().map(|hnsw| &hnsw.config)
+    }
+
+    /// 向量维度，空索引为 0
+    pub fn dims(&self) -> usize {
+        self.dims
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: &str) -> Option<&IndexEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// 插入一条记录，向量会先归一化。id 不要求唯一
+    pub fn insert(&mut self, document: Document, vector: &[f32]) -> Result<(), IndexError> {
+        if self.entries.is_empty() {
+            self.dims = vector.len();
+        } else if vector.len() != self.dims {
+            return Err(IndexError::DimensionMismatch {
+                expected: self.dims,
+                actual: vector.len(),
+            });
+        }
+        self.entries.push(IndexEntry {
+            id: document.id,
+            text: document.text,
+            vector: normalized(vector),
+            metadata: document.metadata,
+        });
+        if let Some(hnsw) = &mut self.hnsw {
+            hnsw.insert(&self.entries, self.entries.len() - 1);
+        }
+        Ok(())
+    }
+
+    /// 插入一批文档和对应的 embeddings 响应，第 i 个文档对应 index 为 i 的向量。
+    /// documents 的顺序需要和请求参数 input 一致，通常配合 `LlmSdk::embeddings` 或 `LlmSdk::embed_all` 使用
+    pub fn insert_response(
+        &mut self,
+        documents: impl IntoIterator<Item = Document>,
+        response: &EmbeddingsResponse,
+    ) -> Result<(), IndexError> {
+        let vectors = response.vectors();
+        for (i, document) in documents.into_iter().enumerate() {
+            let vector = vectors.get(i).ok_or(IndexError::MissingEmbedding(i))?;
+            self.insert(document, vector)?;
+        }
+        Ok(())
+    }
+
+    /// 检索与 query 最相似的 k 条，开启了 HNSW 时为近似结果
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchHit<'_>>, IndexError> {
+        match &self.hnsw {
+            Some(hnsw) => {
+                self.check_dims(query)?;
+                let query = normalized(query);
+                Ok(self.hits(hnsw.search(&self.entries, &query, k)))
+            }
+            None => self.search_exact(query, k),
+        }
+    }
+
+    /// 暴力计算全部记录的余弦相似度，结果精确
+    pub fn search_exact(&self, query: &[f32], k: usize) -> Result<Vec<SearchHit<'_>>, IndexError> {
+        self.check_dims(query)?;
+        let vectors: Vec<&[f32]> = self.entries.iter().map(|entry| entry.vector.as_slice()).collect();
+        Ok(self.hits(top_k(query, &vectors, k)))
+    }
+
+    fn check_dims(&self, query: &[f32]) -> Result<(), IndexError> {
+        if !self.entries.is_empty() && query.len() != self.dims {
+            return Err(IndexError::DimensionMismatch {
+                expected: self.dims,
+                actual: query.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn hits(&self, scores: Vec<(usize, f32)>) -> Vec<SearchHit<'_>> {
+        scores
+            .into_iter()
+            .map(|(i, score)| SearchHit {
+                entry: &self.entries[i],
+                score,
+            })
+            .collect()
+    }
+
+    /// 保存为二进制文件，HNSW 只保存参数，加载时重新建图
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), IndexError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, IndexError> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// 文件格式（整数均为小端序）：
+    /// magic "ARKV" | version u32 | dims u32 | count u64 | hnsw 标记 u8 [m u32, ef_construction u32, ef_search u32, seed u64]
+    /// 之后每条记录依次为 id、text、metadata JSON（均为 u32 长度 + utf-8 字节）和 dims 个 f32
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), IndexError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.dims as u32).to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        match &self.hnsw {
+            Some(hnsw) => {
+                let config = &hnsw.config;
+                writer.write_all(&[1])?;
+                writer.write_all(&(config.m as u32).to_le_bytes())?;
+                writer.write_all(&(config.ef_construction as u32).to_le_bytes())?;
+                writer.write_all(&(config.ef_search as u32).to_le_bytes())?;
+                writer.write_all(&config.seed.to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        for entry in &self.entries {
+            write_bytes(writer, entry.id.as_bytes())?;
+            write_bytes(writer, entry.text.as_bytes())?;
+            let metadata = serde_json::to_vec(&entry.metadata).map_err(|e| IndexError::InvalidFormat(e.to_string()))?;
+            write_bytes(writer, &metadata)?;
+            for value in &entry.vector {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, IndexError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(IndexError::InvalidFormat("bad magic".to_string()));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(IndexError::InvalidFormat(format!("unsupported version {}", version)));
+        }
+        let dims = read_u32(reader)? as usize;
+        let count = read_u64(reader)?;
+        let mut flag = [0u8; 1];
+        reader.read_exact(&mut flag)?;
+        let config = match flag[0] {
+            0 => None,
+            1 => Some(HnswConfig {
+                m: read_u32(reader)? as usize,
+                ef_construction: read_u32(reader)? as usize,
+                ef_search: read_u32(reader)? as usize,
+                seed: read_u64(reader)?,
+            }),
+            other => return Err(IndexError::InvalidFormat(format!("bad hnsw flag {}", other))),
+        };
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let id = read_string(reader)?;
+            let text = read_string(reader)?;
+            let metadata = serde_json::from_slice(&read_bytes(reader)?)
+                .map_err(|e| IndexError::InvalidFormat(format!("bad metadata: {}", e)))?;
+            let bytes = read_exact_len(reader, dims as u64 * 4)?;
+            let vector = bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect();
+            entries.push(IndexEntry {
+                id,
+                text,
+                vector,
+                metadata,
+            });
+        }
+        let index = Self {
+            dims,
+            entries,
+            hnsw: None,
+        };
+        match config {
+            Some(config) => index.with_hnsw(config),
+            None => Ok(index),
+        }
+    }
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>, IndexError> {
+    let len = read_u32(reader)?;
+    read_exact_len(reader, len as u64)
+}
+
+/// 读取 len 个字节。len 来自文件内容，不能直接按它分配内存，损坏的文件可能给出很大的长度
+fn read_exact_len(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, IndexError> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(IndexError::InvalidFormat(format!(
+            "expected {} bytes, file ends after {}",
+            len,
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, IndexError> {
+    String::from_utf8(read_bytes(reader)?).map_err(|e| IndexError::InvalidFormat(e.to_string()))
+}
+
+/// 按相似度排序的候选节点
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    score: f32,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then(other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// HNSW 图，节点编号即 entries 中的下标，neighbors[node][layer] 为该节点在某一层的邻居
+#[derive(Debug, Clone)]
+struct Hnsw {
+    config: HnswConfig,
+    neighbors: Vec<Vec<Vec<usize>>>,
+    entry_point: Option<usize>,
+    rng: fastrand::Rng,
+}
+
+impl Hnsw {
+    fn new(config: HnswConfig) -> Self {
+        Self {
+            config,
+            neighbors: Vec::new(),
+            entry_point: None,
+            rng: fastrand::Rng::with_seed(config.seed),
+        }
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.config.m * 2
+        } else {
+            self.config.m
+        }
+    }
+
+    /// 随机层数，服从参数为 1 / ln(m) 的指数分布
+    fn random_level(&mut self) -> usize {
+        let ml = 1.0 / (self.config.m.max(2) as f64).ln();
+        let r = 1.0 - self.rng.f64();
+        (-r.ln() * ml) as usize
+    }
+
+    fn insert(&mut self, entries: &[IndexEntry], node: usize) {
+        let level = self.random_level();
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+        let Some(mut entry_point) = self.entry_point else {
+            self.entry_point = Some(node);
+            return;
+        };
+        let query = &entries[node].vector;
+        let top = self.neighbors[entry_point].len() - 1;
+        for layer in (level + 1..=top).rev() {
+            entry_point = self.greedy(entries, query, entry_point, layer);
+        }
+        let mut entry_points = vec![entry_point];
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(entries, query, &entry_points, self.config.ef_construction, layer);
+            let selected: Vec<usize> = candidates.iter().take(self.config.m).map(|c| c.node).collect();
+            for &neighbor in &selected {
+                self.neighbors[neighbor][layer].push(node);
+                self.prune(entries, neighbor, layer);
+            }
+            self.neighbors[node][layer] = selected;
+            entry_points = candidates.iter().map(|c| c.node).collect();
+        }
+        if level > top {
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// 邻居数超过上限时只保留最相似的
+    fn prune(&mut self, entries: &[IndexEntry], node: usize, layer: usize) {
+        let max = self.max_neighbors(layer);
+        if self.neighbors[node][layer].len() <= max {
+            return;
+        }
+        let vector = &entries[node].vector;
+        let mut candidates: Vec<Candidate> = self.neighbors[node][layer]
+            .iter()
+            .map(|&neighbor| Candidate {
+                score: dot(vector, &entries[neighbor].vector),
+                node: neighbor,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.cmp(a));
+        self.neighbors[node][layer] = candidates.into_iter().take(max).map(|c| c.node).collect();
+    }
+
+    /// 在一层中贪心地走到与 query 最相似的节点
+    fn greedy(&self, entries: &[IndexEntry], query: &[f32], mut node: usize, layer: usize) -> usize {
+        let mut best = dot(query, &entries[node].vector);
+        loop {
+            let mut changed = false;
+            for &neighbor in &self.neighbors[node][layer] {
+                let score = dot(query, &entries[neighbor].vector);
+                if score > best {
+                    best = score;
+                    node = neighbor;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return node;
+            }
+        }
+    }
+
+    /// 在一层中做 beam search，返回最多 ef 个候选，按相似度从高到低排列
+    fn search_layer(
+        &self,
+        entries: &[IndexEntry],
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        // candidates 取最相似的先扩展，results 是最小堆，堆顶为当前结果中最差的
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &node in entry_points {
+            let candidate = Candidate {
+                score: dot(query, &entries[node].vector),
+                node,
+            };
+            candidates.push(candidate);
+            results.push(std::cmp::Reverse(candidate));
+        }
+        while let Some(current) = candidates.pop() {
+            let worst = results.peek().map_or(f32::MIN, |r| r.0.score);
+            if results.len() >= ef && current.score < worst {
+                break;
+            }
+            for &neighbor in &self.neighbors[current.node][layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let candidate = Candidate {
+                    score: dot(query, &entries[neighbor].vector),
+                    node: neighbor,
+                };
+                let worst = results.peek().map_or(f32::MIN, |r| r.0.score);
+                if results.len() < ef || candidate.score > worst {
+                    candidates.push(candidate);
+                    results.push(std::cmp::Reverse(candidate));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        let mut results: Vec<Candidate> = results.into_iter().map(|r| r.0).collect();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    fn search(&self, entries: &[IndexEntry], query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        let Some(mut entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+        if k == 0 {
+            return Vec::new();
+        }
+        for layer in (1..self.neighbors[entry_point].len()).rev() {
+            entry_point = self.greedy(entries, query, entry_point, layer);
+        }
+        self.search_layer(entries, query, &[entry_point], self.config.ef_search.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|c| (c.node, c.score))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_vectors(count: usize, dims: usize) -> Vec<Vec<f32>> {
+        let mut rng = fastrand::Rng::with_seed(7);
+        (0..count)
+            .map(|_| (0..dims).map(|_| rng.f32() * 2.0 - 1.0).collect())
+            .collect()
+    }
+
+    fn build(vectors: &[Vec<f32>], hnsw: Option<HnswConfig>) -> EmbeddingIndex {
+        let mut index = EmbeddingIndex::new();
+        if let Some(config) = hnsw {
+            index = index.with_hnsw(config).unwrap();
+        }
+        for (i, vector) in vectors.iter().enumerate() {
+            let document = Document::new(format!("doc-{}", i), format!("第 {} 篇文档", i))
+                .with_metadata(serde_json::json!({ "page": i }));
+            index.insert(document, vector).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn exact_search_should_work_on_fixture() {
+        let res: EmbeddingsResponse = serde_json::from_str(include_str!("../example.txt")).unwrap();
+        let mut index = EmbeddingIndex::new();
+        index
+            .insert_response(vec![Document::new("sky", "天很蓝"), Document::new("sea", "海很深")], &res)
+            .unwrap();
+        assert_eq!(index.dims(), 4096);
+
+        let hits = index.search(res.vectors()[1], 2).unwrap();
+        assert_eq!(hits[0].entry.id, "sea");
+        assert!((hits[0].score - 1.0).abs() < 1e-4);
+        assert_eq!(hits[1].entry.id, "sky");
+
+        let err = index.search(&[1.0, 0.0], 1).unwrap_err();
+        assert!(matches!(err, IndexError::DimensionMismatch { expected: 4096, actual: 2 }));
+        let err = index
+            .insert_response(vec![Document::new("a", "a"), Document::new("b", "b"), Document::new("c", "c")], &res)
+            .unwrap_err();
+        assert!(matches!(err, IndexError::MissingEmbedding(2)));
+    }
+
+    #[test]
+    fn hnsw_should_match_exact_search() {
+        let vectors = random_vectors(1000, 32);
+        let index = build(&vectors, Some(HnswConfig::default()));
+        let queries = random_vectors(1020, 32).split_off(1000);
+        let mut found = 0;
+        for query in &queries {
+            let exact: HashSet<&str> = index
+                .search_exact(query, 10)
+                .unwrap()
+                .iter()
+                .map(|hit| hit.entry.id.as_str())
+                .collect();
+            let approx = index.search(query, 10).unwrap();
+            assert_eq!(approx.len(), 10);
+            assert!(approx.windows(2).all(|w| w[0].score >= w[1].score));
+            found += approx.iter().filter(|hit| exact.contains(hit.entry.id.as_str())).count();
+        }
+        // 召回率不低于 90%
+        assert!(found * 10 >= queries.len() * 10 * 9, "recall {}", found);
+    }
+
+    #[test]
+    fn index_should_save_and_load() {
+        let vectors = random_vectors(50, 16);
+        let index = build(&vectors, Some(HnswConfigBuilder::default().m(8).build().unwrap()));
+        let path = std::env::temp_dir().join(format!("llm-sdk-index-{}.bin", std::process::id()));
+        index.save(&path).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        let loaded = EmbeddingIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // 向量按 f32 原样保存，文本和 metadata 只多出长度前缀
+        assert!(size < 50 * (16 * 4 + 64) + 64);
+        assert_eq!(loaded.entries(), index.entries());
+        assert_eq!(loaded.hnsw_config(), index.hnsw_config());
+        assert_eq!(loaded.get("doc-3").unwrap().metadata["page"], 3);
+        let ids = |index: &EmbeddingIndex| -> Vec<String> {
+            index.search(&vectors[3], 5).unwrap().iter().map(|hit| hit.entry.id.clone()).collect()
+        };
+        assert_eq!(ids(&loaded), ids(&index));
+
+        let err = EmbeddingIndex::read_from(&mut &b"NOPE"[..]).unwrap_err();
+        assert!(matches!(err, IndexError::InvalidFormat(_)));
+    }
+
+    #[test]
+    fn hnsw_config_should_be_validated() {
+        assert!(HnswConfigBuilder::default().m(0).build().is_err());
+        assert!(HnswConfigBuilder::default().ef_construction(0).build().is_err());
+        let config = HnswConfig { m: 0, ..Default::default() };
+        let err = EmbeddingIndex::new().with_hnsw(config).unwrap_err();
+        assert!(matches!(err, IndexError::InvalidConfig(_)));
+
+        // 和 SDK 的调用混用时可以直接用 ?
+        let err: crate::LlmError = err.into();
+        assert_eq!(err.kind(), "index");
+    }
+
+    #[test]
+    fn corrupted_lengths_should_not_allocate() {
+        let mut bytes = Vec::new();
+        build(&random_vectors(1, 4), None).write_to(&mut bytes).unwrap();
+        // 头部：magic(4) + version(4) + dims(4) + count(8) + hnsw flag(1)，之后是第一个 id 的长度
+        let header = 4 + 4 + 4 + 8 + 1;
+
+        let mut huge_string = bytes.clone();
+        huge_string[header..header + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = EmbeddingIndex::read_from(&mut huge_string.as_slice()).unwrap_err();
+        assert!(matches!(err, IndexError::InvalidFormat(_)), "{:?}", err);
+
+        let mut huge_dims = bytes.clone();
+        huge_dims[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = EmbeddingIndex::read_from(&mut huge_dims.as_slice()).unwrap_err();
+        assert!(matches!(err, IndexError::InvalidFormat(_)), "{:?}", err);
+
+        bytes.truncate(bytes.len() - 1);
+        let err = EmbeddingIndex::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert!(matches!(err, IndexError::InvalidFormat(_)), "{:?}", err);
+    }
+}
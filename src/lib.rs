@@ -1,155 +1,606 @@
 pub mod api;
+pub mod batch;
+pub mod cache;
+pub mod config;
+pub mod conversation;
+pub mod error;
+pub mod index;
+pub mod limiter;
+pub mod meta;
+pub mod redact;
+pub mod retry;
+pub mod schema;
+pub mod sse;
+pub mod telemetry;
+pub mod tools;
+pub mod vector;
 
-use anyhow::{anyhow, Result};
 use api::*;
 use derive_builder::Builder;
-use reqwest::{Client, RequestBuilder, Response};
-use std::time::Duration;
-use tracing::{error, info};
-use chat_completion::{ChatCompletionChunkResponse, ChatCompletionRequest, ChatCompletionResponse};
+use futures::{Stream, StreamExt};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Client, RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use telemetry::{GenAiResponse, Operation};
+use tracing::{debug, error, trace, warn, Instrument, Level};
+use chat_completion::{ChatCompletionChunkResponse, ChatCompletionRequest, ChatCompletionResponse, ResponseFormat};
 use vision_lite::{VisionLiteRequest, VisionLiteResponse};
 use vision_pro::{VisionProRequest, VisionProResponse};
 use embeddings::{EmbeddingsRequest, EmbeddingsResponse};
+use multimodal_embeddings::{MultimodalEmbeddingsRequest, MultimodalEmbeddingsResponse};
+use limiter::RatePermit;
 
-const TIMEOUT: u64 = 120;
-const LINE_FEED: u8 = 10;
-const SEARCH_TAIL: usize = 10;
-const LINE_FEED_COUNT: usize = 2;
-const LEFT_SIGN: u8 = 123;
+pub use cache::{EmbeddingCache, FileCache, MemoryCache};
+pub use config::{HttpConfig, Region};
+pub use conversation::Conversation;
+pub use error::{ArkError, LlmError, Result};
+pub use limiter::{RateLimiter, RateLimits};
+pub use meta::{RequestOptions, ResponseMeta, WithMeta};
+pub use redact::{Redactor, SecretString};
+pub use retry::RetryPolicy;
+pub use schema::ArgumentsError;
+pub use tools::{ToolRegistry, ToolRun};
 
 #[derive(Debug, Clone, Builder)]
+#[builder(build_fn(skip))]
 pub struct LlmSdk {
-    #[builder(setter(into), default = r#""/api/v3/chat/completions".into()"#)]
-    pub(crate) base_url: String,
-    pub(crate) key: String,
+    /// 接口地址前缀，比如 https://ark.cn-beijing.volces.com/api/v3/
+    #[builder(setter(custom), field(ty = "Option<String>"))]
+    pub(crate) base_url: Url,
+    /// API Key，Debug 输出时会被隐藏
+    #[builder(setter(into))]
+    pub(crate) key: SecretString,
+    /// HTTP 客户端配置，通过 client 传入自己的客户端时不生效
+    pub(crate) http: HttpConfig,
+    /// 所有请求共享的客户端，clone LlmSdk 时共用同一个连接池
+    pub(crate) client: Client,
+    /// 失败重试策略
+    pub(crate) retry: RetryPolicy,
+    /// 客户端限流器，多个 LlmSdk 可以共用一个
+    #[builder(setter(strip_option))]
+    pub(crate) limiter: Option<Arc<RateLimiter>>,
+    /// debug/trace 日志中输出请求和响应内容前的脱敏处理
+    pub(crate) redactor: Redactor,
+    /// embeddings 缓存，设置后只请求缓存中没有的文本
+    #[builder(setter(strip_option))]
+    pub(crate) embedding_cache: Option<Arc<dyn EmbeddingCache>>,
 }
 
 pub trait MessageEvent {
     fn on_message(&self, chat_completion: &ChatCompletionChunkResponse);
     fn on_end(&self);
+
+    /// 收到思考过程片段，index 为 choice 的索引
+    fn on_reasoning(&self, _index: usize, _reasoning: &str) {}
+
+    /// 收到回答内容片段，index 为 choice 的索引
+    fn on_content(&self, _index: usize, _content: &str) {}
+}
+
+impl LlmSdkBuilder {
+    pub fn build(&self) -> std::result::Result<LlmSdk, LlmSdkBuilderError> {
+        let key = self
+            .key
+            .clone()
+            .ok_or(LlmSdkBuilderError::UninitializedField("key"))?;
+        let base_url = match &self.base_url {
+            Some(base_url) => config::parse_base_url(base_url),
+            None => config::parse_base_url(&Region::default().base_url()),
+        }
+        .map_err(|e| LlmSdkBuilderError::ValidationError(e.to_string()))?;
+        let http = self.http.clone().unwrap_or_default();
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => http
+                .build_client()
+                .map_err(|e| LlmSdkBuilderError::ValidationError(e.to_string()))?,
+        };
+        Ok(LlmSdk {
+            base_url,
+            key,
+            http,
+            client,
+            retry: self.retry.clone().unwrap_or_default(),
+            limiter: self.limiter.clone().flatten(),
+            redactor: self.redactor.clone().unwrap_or_default(),
+            embedding_cache: self.embedding_cache.clone().flatten(),
+        })
+    }
+
+    /// 接口地址前缀，默认为华北 2（北京）的 HTTPS 地址，可以指向本地的 mock 服务
+    pub fn base_url(&mut self, base_url: impl Into<String>) -> &mut Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// 按地域设置接口地址
+    pub fn region(&mut self, region: Region) -> &mut Self {
+        self.base_url = Some(region.base_url());
+        self
+    }
+
+    fn http_mut(&mut self) -> &mut HttpConfig {
+        self.http.get_or_insert_with(HttpConfig::default)
+    }
+
+    /// 建立连接的超时时间
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.http_mut().connect_timeout = timeout;
+        self
+    }
+
+    /// 单次请求的超时时间
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.http_mut().timeout = timeout;
+        self
+    }
+
+    /// 每个 host 最多保留的空闲连接数
+    pub fn pool_max_idle_per_host(&mut self, max_idle: usize) -> &mut Self {
+        self.http_mut().pool_max_idle_per_host = Some(max_idle);
+        self
+    }
+
+    /// 空闲连接的保留时长
+    pub fn pool_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.http_mut().pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// 直接使用 HTTP/2 建立连接
+    pub fn http2_prior_knowledge(&mut self, enabled: bool) -> &mut Self {
+        self.http_mut().http2_prior_knowledge = enabled;
+        self
+    }
+
+    /// 代理地址
+    pub fn proxy(&mut self, proxy: impl Into<String>) -> &mut Self {
+        self.http_mut().proxy = Some(proxy.into());
+        self
+    }
+
+    /// User-Agent 请求头
+    pub fn user_agent(&mut self, user_agent: impl Into<String>) -> &mut Self {
+        self.http_mut().user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// 追加一个每个请求都会带上的请求头
+    pub fn default_header(&mut self, name: HeaderName, value: HeaderValue) -> &mut Self {
+        self.http_mut().default_headers.insert(name, value);
+        self
+    }
 }
 
 impl LlmSdk {
     pub fn new(key: String) -> Self {
+        let http = HttpConfig::default();
+        let client = http.build_client().expect("failed to build default http client");
         Self {
-            key,
-            base_url: "http://ark.cn-beijing.volces.com/api/v3".to_string(),
+            key: key.into(),
+            base_url: config::parse_base_url(&Region::default().base_url())
+                .expect("default base url is valid"),
+            http,
+            client,
+            retry: RetryPolicy::default(),
+            limiter: None,
+            redactor: Redactor::default(),
+            embedding_cache: None,
+        }
+    }
+
+    /// 当前使用的接口地址前缀
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        config::join_url(&self.base_url, path)
+    }
+
+    /// 当前使用的 HTTP 客户端配置
+    pub fn http_config(&self) -> &HttpConfig {
+        &self.http
+    }
+
+    /// 当前使用的重试策略
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// 配置了限流器时，排队等待发送许可
+    async fn acquire(&self, model: &str, estimated_tokens: u32) -> Option<RatePermit> {
+        match &self.limiter {
+            Some(limiter) => Some(limiter.acquire(model, estimated_tokens).await),
+            None => None,
+        }
+    }
+
+    /// 按重试策略发送请求，只在拿到成功响应之前重试
+    async fn send_with_retry(&self, request_build: RequestBuilder) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let current = request_build
+                .try_clone()
+                .ok_or_else(|| LlmError::Config("request body can not be cloned".to_string()))?;
+            match current.send_and_log().await {
+                Ok(res) => return Ok(res),
+                Err(e) if attempt < self.retry.max_attempts && self.retry.should_retry(&e) => {
+                    let delay = self.retry.delay(attempt, &e);
+                    warn!("attempt {} failed, retry in {:?}: {}", attempt, delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 构造 POST 请求，带上鉴权和自定义请求 ID
+    fn request(&self, url: Url, options: &RequestOptions) -> RequestBuilder {
+        let mut request_build = self.client.post(url).bearer_auth(self.key.expose_secret());
+        if let Some(client_request_id) = &options.client_request_id {
+            request_build = request_build.header(meta::CLIENT_REQUEST_ID_HEADER, client_request_id);
+        }
+        request_build
+    }
+
+    /// 在 debug 级别输出脱敏后的请求体
+    fn log_request(&self, operation: &str, req: &impl Serialize) {
+        if tracing::enabled!(Level::DEBUG) {
+            if let Ok(body) = serde_json::to_value(req) {
+                debug!("{} request: {}", operation, self.redactor.redact_json(&body));
+            }
         }
     }
 
+    /// 发送请求并读取 JSON 响应，同时记录响应元信息
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        operation: &str,
+        request_build: RequestBuilder,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<T>> {
+        let start = Instant::now();
+        let res = self.send_with_retry(request_build).await?;
+        debug!("{} response: {:?}", operation, res);
+        let status = res.status();
+        let headers = res.headers().clone();
+        // 读取完整响应体再反序列化，失败时错误里带上原始内容
+        let text = res.text().await?;
+        trace!("{} response body: {}", operation, self.redactor.redact_json_str(&text));
+        let data = serde_json::from_str::<T>(&text).map_err(|e| LlmError::deserialize(e, text))?;
+        let mut meta = ResponseMeta::from_headers(status, &headers, start.elapsed());
+        if meta.client_request_id.is_none() {
+            meta.client_request_id = options.client_request_id.clone();
+        }
+        Ok(WithMeta { data, meta })
+    }
+
     pub async fn chat_completion(
         &self,
         req: &ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse> {
-        let url = format!("{}/chat/completions", self.base_url);
-        info!("url:{}", url);
-        let client = Client::new();
-        let request_build = client
-            .post(url)
-            .json(req)
-            .bearer_auth(&self.key)
-            .timeout(Duration::from_secs(TIMEOUT));
-        let res = request_build.send_and_log().await?;
-        info!("chat completion response: {:?}", res);
-        Ok(res.json::<ChatCompletionResponse>().await?)
+        self.chat_completion_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
     }
 
-    pub async fn chat_completion_stream(
+    /// 同 chat_completion，额外返回 request id、限流信息和耗时
+    pub async fn chat_completion_with_meta(
         &self,
         req: &ChatCompletionRequest,
-        event: &impl MessageEvent,
-    ) -> Result<()> {
-        let url = format!("{}/chat/completions", self.base_url);
-        info!("url:{}", url);
-        let client = Client::new();
-        let request_build = client
-            .post(url)
-            .json(req)
-            .bearer_auth(&self.key)
-            .timeout(Duration::from_secs(TIMEOUT));
-        let mut res = request_build.send().await?;
-        info!("chat completion stream response: {:?}", res);
-        while let Some(chunk) = res.chunk().await? {
-            info!("chunk:{:?}", chunk);
-            // 多帧的处理
-            let chunk_len = chunk.len();
-            // 让搜索少一点吧
-            let search_len = chunk_len / 2 + SEARCH_TAIL;
-            let mut line_count = 0;
-            //let mut last_pos = 0;
-            for i in 0..search_len {
-                // 找出换行，查看后面是否还有数据
-                if chunk[i] == LINE_FEED {
-                    if i < chunk_len - LINE_FEED_COUNT {
-                        info!("multi frame: {},{}", i, chunk[i + LINE_FEED_COUNT]);
-                        let last_pos = i;
-                        if (last_pos + 1) == i {
-                            line_count = line_count + 1;
+        options: &RequestOptions,
+    ) -> Result<WithMeta<ChatCompletionResponse>> {
+        let span = telemetry::call_span(Operation::Chat, "chat_completion", req.model(), false, &self.base_url);
+        let res = async {
+            let url = self.endpoint("chat/completions")?;
+            let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+            self.log_request("chat completion", req);
+            let request_build = self.request(url, options).json(req);
+            let res = self
+                .send_json::<ChatCompletionResponse>("chat completion", request_build, options)
+                .await;
+            if let Some(permit) = &permit {
+                match &res {
+                    Ok(res) => {
+                        if let Some(usage) = &res.usage {
+                            permit.settle(usage.total_tokens as u32);
                         }
                     }
+                    // 请求失败时退还预估的 token
+                    Err(_) => permit.settle(0),
                 }
             }
+            res
+        }
+        .instrument(span.clone())
+        .await;
+        telemetry::record_result(&span, &res);
+        res
+    }
 
-            //找到大括号，把前面的data:去掉
-            let mut pos = 0;
-            for i in 0..chunk.len() {
-                if chunk.get(i).unwrap().eq(&LEFT_SIGN) {
-                    pos = i;
-                    break;
+    /// 让模型输出 JSON 并解析成 T。请求没有设置 response_format 时使用 json_object，
+    /// 需要约束结构时设置 json_schema，或者开启 schema feature 后使用 [`LlmSdk::chat_completion_typed`]。
+    /// 解析失败时返回 `LlmError::Output`，其中带有模型的原始输出
+    pub async fn chat_completion_structured<T: DeserializeOwned>(
+        &self,
+        req: &ChatCompletionRequest,
+    ) -> Result<T> {
+        self.send_structured(req, || ResponseFormat::JsonObject).await
+    }
+
+    /// 同 chat_completion_structured，但请求没有设置 response_format 时按 T 生成 json_schema 发送，
+    /// 模型按 T 的结构输出。请求中已经设置的 response_format 保持不变
+    #[cfg(feature = "schema")]
+    pub async fn chat_completion_typed<T: DeserializeOwned + schemars::JsonSchema>(
+        &self,
+        req: &ChatCompletionRequest,
+    ) -> Result<T> {
+        self.send_structured(req, || ResponseFormat::from_type::<T>(T::schema_name()))
+            .await
+    }
+
+    /// 请求没有设置 response_format 时使用 default_format
+    async fn send_structured<T: DeserializeOwned>(
+        &self,
+        req: &ChatCompletionRequest,
+        default_format: impl FnOnce() -> ResponseFormat,
+    ) -> Result<T> {
+        let res = if req.response_format.is_none() {
+            let mut req = req.clone();
+            req.response_format = Some(default_format());
+            self.chat_completion(&req).await?
+        } else {
+            self.chat_completion(req).await?
+        };
+        res.parse_content()
+    }
+
+    /// 以 `MessageEvent` 回调的方式消费流式输出，内部基于 [`LlmSdk::chat_completion_chunk_stream`]
+    pub async fn chat_completion_stream(
+        &self,
+        req: &ChatCompletionRequest,
+        event: &impl MessageEvent,
+    ) -> Result<()> {
+        let mut stream = pin!(self.chat_completion_chunk_stream(req).await?);
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            event.on_message(&chunk);
+            for choice in &chunk.choices {
+                let Some(delta) = &choice.delta else {
+                    continue;
+                };
+                if let Some(reasoning) = delta.reasoning_content.as_deref().filter(|s| !s.is_empty()) {
+                    event.on_reasoning(choice.index, reasoning);
+                }
+                if let Some(content) = delta.content.as_deref().filter(|s| !s.is_empty()) {
+                    event.on_content(choice.index, content);
                 }
             }
-            let chat_completion: ChatCompletionChunkResponse =
-                serde_json::from_slice(&chunk[pos..])?;
-            event.on_message(&chat_completion);
         }
         event.on_end();
         Ok(())
     }
 
+    /// 流式输出，返回的 Stream 在收到 `data: [DONE]` 后结束，drop 掉即可取消请求
+    pub async fn chat_completion_chunk_stream(
+        &self,
+        req: &ChatCompletionRequest,
+    ) -> Result<impl Stream<Item = Result<ChatCompletionChunkResponse>> + Send + 'static> {
+        self.chat_completion_chunk_stream_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
+    }
+
+    /// 同 chat_completion_chunk_stream，额外返回 request id、限流信息和耗时。
+    /// 元信息在收到响应头时生成，latency 为拿到响应头的耗时，不包含读取流的时间
+    pub async fn chat_completion_chunk_stream_with_meta(
+        &self,
+        req: &ChatCompletionRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<impl Stream<Item = Result<ChatCompletionChunkResponse>> + Send + 'static>> {
+        let span = telemetry::call_span(Operation::Chat, "chat_completion_chunk_stream", req.model(), true, &self.base_url);
+        let start = Instant::now();
+        let setup = async {
+            let url = self.endpoint("chat/completions")?;
+            let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+            self.log_request("chat completion stream", req);
+            let request_build = self.request(url, options).json(req);
+            let res = self.send_with_retry(request_build).await;
+            if let (Some(permit), Err(_)) = (&permit, &res) {
+                permit.settle(0);
+            }
+            let res = res?;
+            debug!("chat completion stream response: {:?}", res);
+            Ok((permit, res))
+        }
+        .instrument(span.clone())
+        .await;
+        let (permit, res) = setup.inspect_err(|e| telemetry::record_error(&span, e))?;
+        let mut meta = ResponseMeta::from_headers(res.status(), res.headers(), start.elapsed());
+        if meta.client_request_id.is_none() {
+            meta.client_request_id = options.client_request_id.clone();
+        }
+        let mut first_chunk = true;
+        let mut finish_reasons = Vec::new();
+        // 许可和 span 跟着 Stream 走，流结束或者被 drop 时才释放并发名额、结束 span
+        let stream = sse::json_stream(res.bytes_stream(), self.redactor.clone()).map(move |chunk: Result<ChatCompletionChunkResponse>| {
+            match &chunk {
+                Ok(chunk) => {
+                    if first_chunk {
+                        first_chunk = false;
+                        telemetry::record_time_to_first_token(&span, start.elapsed());
+                    }
+                    chunk.record(&span);
+                    finish_reasons.extend(chunk.choices.iter().filter_map(|c| c.finish_reason.clone()));
+                    telemetry::record_finish_reasons(&span, finish_reasons.iter().map(String::as_str));
+                    if let (Some(permit), Some(usage)) = (&permit, &chunk.usage) {
+                        permit.settle(usage.total_tokens as u32);
+                    }
+                }
+                Err(e) => telemetry::record_error(&span, e),
+            }
+            chunk
+        });
+        Ok(WithMeta { data: stream, meta })
+    }
+
     pub async fn vision_lite(&self, req: &VisionLiteRequest) -> Result<VisionLiteResponse> {
-        let url = format!("{}/chat/completions", self.base_url);
-        info!("url:{}", url);
-        let client = Client::new();
-        let request_build = client
-            .post(url)
-            .json(req)
-            .bearer_auth(&self.key)
-            .timeout(Duration::from_secs(TIMEOUT));
-        let res = request_build.send_and_log().await?;
-        info!("vision lite response: {:?}", res);
-        Ok(res.json::<VisionLiteResponse>().await?)
+        self.vision_lite_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
+    }
+
+    /// 同 vision_lite，额外返回 request id、限流信息和耗时
+    pub async fn vision_lite_with_meta(
+        &self,
+        req: &VisionLiteRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<VisionLiteResponse>> {
+        let span = telemetry::call_span(Operation::Chat, "vision_lite", req.model(), false, &self.base_url);
+        let res = async {
+            let url = self.endpoint("chat/completions")?;
+            let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+            self.log_request("vision lite", req);
+            let request_build = self.request(url, options).json(req);
+            let res = self
+                .send_json::<VisionLiteResponse>("vision lite", request_build, options)
+                .await;
+            if let Some(permit) = &permit {
+                permit.settle(res.as_ref().map_or(0, |res| res.usage.total_tokens as u32));
+            }
+            res
+        }
+        .instrument(span.clone())
+        .await;
+        telemetry::record_result(&span, &res);
+        res
     }
 
     pub async fn vision_pro(&self, req: &VisionProRequest) -> Result<VisionProResponse> {
-        let url = format!("{}/chat/completions", self.base_url);
-        info!("url:{}", url);
-        let client = Client::new();
-        let request_build = client
-            .post(url)
-            .json(req)
-            .bearer_auth(&self.key)
-            .header("x-ark-beta-vision", "true")
-            .timeout(Duration::from_secs(TIMEOUT));
-        let res = request_build.send_and_log().await?;
-        info!("vision pro response: {:?}", res);
-        Ok(res.json::<VisionProResponse>().await?)
+        self.vision_pro_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
     }
 
+    /// 同 vision_pro，额外返回 request id、限流信息和耗时
+    pub async fn vision_pro_with_meta(
+        &self,
+        req: &VisionProRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<VisionProResponse>> {
+        let span = telemetry::call_span(Operation::Chat, "vision_pro", req.model(), false, &self.base_url);
+        let res = async {
+            let url = self.endpoint("chat/completions")?;
+            let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+            self.log_request("vision pro", req);
+            let request_build = self
+                .request(url, options)
+                .json(req)
+                .header("x-ark-beta-vision", "true");
+            let res = self
+                .send_json::<VisionProResponse>("vision pro", request_build, options)
+                .await;
+            if let Some(permit) = &permit {
+                permit.settle(res.as_ref().map_or(0, |res| res.usage.total_tokens as u32));
+            }
+            res
+        }
+        .instrument(span.clone())
+        .await;
+        telemetry::record_result(&span, &res);
+        res
+    }
 
     pub async fn embeddings(&self, req: &EmbeddingsRequest) -> Result<EmbeddingsResponse> {
-        let url = format!("{}/embeddings", self.base_url);
-        info!("url:{}", url);
-        let client = Client::new();
-        let request_build = client
-            .post(url)
-            .json(req)
-            .bearer_auth(&self.key)
-            .timeout(Duration::from_secs(TIMEOUT));
-        let res = request_build.send_and_log().await?;
-        // for test
-        //let result_str: String = res.text().await.unwrap();
-        info!("embedding response:{:?}", res);
-        Ok(res.json::<EmbeddingsResponse>().await?)
+        self.embeddings_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
+    }
+
+    /// 同 embeddings，额外返回 request id、限流信息和耗时
+    pub async fn embeddings_with_meta(
+        &self,
+        req: &EmbeddingsRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<EmbeddingsResponse>> {
+        let span = telemetry::call_span(Operation::Embeddings, "embeddings", req.model(), false, &self.base_url);
+        let res = async {
+            match &self.embedding_cache {
+                Some(cache) => self.cached_embeddings(cache.as_ref(), req, options).await,
+                None => self.send_embeddings(req, options).await,
+            }
+        }
+        .instrument(span.clone())
+        .await;
+        telemetry::record_result(&span, &res);
+        res
+    }
+
+    /// 文本和图片混合输入的向量化，需要使用 doubao-embedding-vision 等多模态向量模型。
+    /// 不经过 embedding_cache
+    pub async fn multimodal_embeddings(
+        &self,
+        req: &MultimodalEmbeddingsRequest,
+    ) -> Result<MultimodalEmbeddingsResponse> {
+        self.multimodal_embeddings_with_meta(req, &RequestOptions::default())
+            .await
+            .map(WithMeta::into_inner)
+    }
+
+    /// 同 multimodal_embeddings，额外返回 request id、限流信息和耗时
+    pub async fn multimodal_embeddings_with_meta(
+        &self,
+        req: &MultimodalEmbeddingsRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<MultimodalEmbeddingsResponse>> {
+        let span = telemetry::call_span(
+            Operation::Embeddings,
+            "multimodal_embeddings",
+            req.model(),
+            false,
+            &self.base_url,
+        );
+        let res = async {
+            let url = self.endpoint("embeddings/multimodal")?;
+            let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+            self.log_request("multimodal embedding", req);
+            let request_build = self.request(url, options).json(req);
+            let res = self
+                .send_json::<MultimodalEmbeddingsResponse>("multimodal embedding", request_build, options)
+                .await;
+            if let Some(permit) = &permit {
+                permit.settle(res.as_ref().map_or(0, |res| res.usage.total_tokens));
+            }
+            res
+        }
+        .instrument(span.clone())
+        .await;
+        telemetry::record_result(&span, &res);
+        res
+    }
+
+    pub(crate) async fn send_embeddings(
+        &self,
+        req: &EmbeddingsRequest,
+        options: &RequestOptions,
+    ) -> Result<WithMeta<EmbeddingsResponse>> {
+        let url = self.endpoint("embeddings")?;
+        let permit = self.acquire(req.model(), req.estimate_tokens()).await;
+        self.log_request("embedding", req);
+        let request_build = self.request(url, options).json(req);
+        let res = self
+            .send_json::<EmbeddingsResponse>("embedding", request_build, options)
+            .await;
+        if let Some(permit) = &permit {
+            permit.settle(res.as_ref().map_or(0, |res| res.usage.total_tokens));
+        }
+        res
     }
 }
 
@@ -162,24 +613,436 @@ impl SendAndLog for RequestBuilder {
         let res = self.send().await?;
         let status = res.status();
         if status.is_client_error() || status.is_server_error() {
-            info!("status: {}", status);
+            let headers = res.headers().clone();
             let text = res.text().await?;
-            error!("API failed: {}", text);
-            return Err(anyhow!("API failed: {}", text));
+            let err = LlmError::from_http(status, &headers, &text);
+            error!(status = status.as_u16(), kind = err.kind(), "{}", err);
+            return Err(err);
         }
         Ok(res)
     }
 }
 
-
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
     use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
     
     #[ctor::ctor]
     fn init() {
         tracing_subscriber::registry().with(fmt::layer()).init();
     }
+
+    /// 在本地起一个简单的 HTTP 服务，按顺序返回给定的响应（最后一个会一直重复），
+    /// 返回 base_url 和收到的原始请求
+    pub(crate) async fn mock_server(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            let mut index = 0;
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let response = responses[index.min(responses.len() - 1)].clone();
+                index += 1;
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut socket).await;
+                    received.lock().unwrap().push(request);
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        (format!("http://{}/api/v3", addr), requests)
+    }
+
+    async fn read_request(socket: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut tmp = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut tmp).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&tmp[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(pos) = text.find("\r\n\r\n") {
+                let content_length = text[..pos]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if buf.len() >= pos + 4 + content_length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&buf).to_string()
+    }
+
+    pub(crate) fn http_response(status: u16, headers: &[(&str, &str)], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {} MOCK\r\ncontent-length: {}\r\n", status, body.len());
+        for (name, value) in headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("connection: close\r\n\r\n");
+        response.push_str(body);
+        response
+    }
+
+    pub(crate) fn mock_sdk(base_url: &str) -> LlmSdk {
+        LlmSdkBuilder::default()
+            .key("test-key")
+            .base_url(base_url)
+            .retry(
+                retry::RetryPolicyBuilder::default()
+                    .base_delay(Duration::from_millis(1))
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    pub(crate) const CHAT_COMPLETION_RESPONSE: &str = r#"{"id":"021718067849899d92fcbe0865fdffdde","model":"doubao-pro-32k-240515","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"你好，我是豆包"},"logprobs":null}],"usage":{"prompt_tokens":12,"completion_tokens":6,"total_tokens":18}}"#;
+
+    pub(crate) fn chat_request() -> ChatCompletionRequest {
+        chat_completion::ChatCompletionRequestBuilder::default()
+            .model("ep-20240817170913-w9q57".to_string())
+            .messages(vec![chat_completion::ChatCompletionMessage::User(
+                chat_completion::UserMessage {
+                    content: "你是谁".to_string(),
+                },
+            )])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_completion_with_mock_server_should_work() {
+        let (base_url, requests) =
+            mock_server(vec![http_response(200, &[], CHAT_COMPLETION_RESPONSE)]).await;
+        let sdk = mock_sdk(&base_url);
+        let res = sdk.chat_completion(&chat_request()).await.unwrap();
+        assert_eq!(res.choices[0].message.content.as_deref(), Some("你好，我是豆包"));
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("POST /api/v3/chat/completions HTTP/1.1"));
+        assert!(requests[0].contains("authorization: Bearer test-key"));
+    }
+
+    #[tokio::test]
+    async fn response_meta_should_be_returned() {
+        let response = http_response(
+            200,
+            &[
+                ("x-request-id", "021718067849899d92fcbe0865fdffdde"),
+                ("x-ratelimit-remaining-requests", "29999"),
+            ],
+            CHAT_COMPLETION_RESPONSE,
+        );
+        let (base_url, requests) = mock_server(vec![response]).await;
+        let options = meta::RequestOptionsBuilder::default()
+            .client_request_id("trace-42")
+            .build()
+            .unwrap();
+        let res = mock_sdk(&base_url)
+            .chat_completion_with_meta(&chat_request(), &options)
+            .await
+            .unwrap();
+        assert_eq!(res.meta.status, reqwest::StatusCode::OK);
+        assert_eq!(res.meta.request_id.as_deref(), Some("021718067849899d92fcbe0865fdffdde"));
+        assert_eq!(res.meta.client_request_id.as_deref(), Some("trace-42"));
+        assert_eq!(res.meta.rate_limit.remaining_requests, Some(29999));
+        assert_eq!(res.object, "chat.completion");
+        assert!(requests.lock().unwrap()[0].contains("x-client-request-id: trace-42"));
+    }
+
+    fn content_response(content: &str, finish_reason: &str) -> String {
+        let body = serde_json::json!({
+            "id": "0217", "model": "doubao-pro-32k-240515", "object": "chat.completion", "created": 1718067849,
+            "choices": [{"index": 0, "finish_reason": finish_reason, "message": {"role": "assistant", "content": content}}],
+        });
+        http_response(200, &[], &body.to_string())
+    }
+
+    #[tokio::test]
+    async fn structured_output_should_be_parsed() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Person {
+            name: String,
+            age: u32,
+        }
+        let (base_url, requests) = mock_server(vec![
+            content_response(r#"{"name":"张三","age":18}"#, "stop"),
+            content_response("```json\n{\"name\":\"李四\",\"age\":20}\n```", "stop"),
+            content_response(r#"{"name":"王五","#, "length"),
+        ])
+        .await;
+        let sdk = mock_sdk(&base_url);
+        let person: Person = sdk.chat_completion_structured(&chat_request()).await.unwrap();
+        assert_eq!(person, Person { name: "张三".to_string(), age: 18 });
+        assert!(requests.lock().unwrap()[0].contains(r#""response_format":{"type":"json_object"}"#));
+
+        let schema = serde_json::json!({"type": "object", "properties": {"name": {"type": "string"}, "age": {"type": "integer"}}});
+        let req = chat_completion::ChatCompletionRequestBuilder::default()
+            .model("ep-20240817170913-w9q57".to_string())
+            .messages(chat_request().messages().to_vec())
+            .response_format(ResponseFormat::json_schema("person", schema))
+            .build()
+            .unwrap();
+        let person: Person = sdk.chat_completion_structured(&req).await.unwrap();
+        assert_eq!(person.name, "李四");
+        assert!(requests.lock().unwrap()[1].contains(r#""response_format":{"type":"json_schema","json_schema":{"name":"person""#));
+
+        let err = sdk.chat_completion_structured::<Person>(&chat_request()).await.unwrap_err();
+        match err {
+            LlmError::Output { text, finish_reason, .. } => {
+                assert_eq!(text, r#"{"name":"王五","#);
+                assert_eq!(finish_reason.as_deref(), Some("length"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "schema")]
+    #[tokio::test]
+    async fn typed_output_should_send_schema_of_type() {
+        #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+        struct City {
+            /// 城市名称
+            name: String,
+            population: u64,
+        }
+        let (base_url, requests) = mock_server(vec![content_response(r#"{"name":"北京","population":21893095}"#, "stop")]).await;
+        let sdk = mock_sdk(&base_url);
+        let city: City = sdk.chat_completion_typed(&chat_request()).await.unwrap();
+        assert_eq!(city.name, "北京");
+        assert_eq!(city.population, 21893095);
+
+        let request = requests.lock().unwrap()[0].clone();
+        let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        let format = &body["response_format"];
+        assert_eq!(format["type"], "json_schema");
+        assert_eq!(format["json_schema"]["name"], "City");
+        assert_eq!(format["json_schema"]["schema"], schema::json_schema_for::<City>());
+        assert_eq!(format["json_schema"]["schema"]["properties"]["name"]["description"], "城市名称");
+
+        // 显式设置 json_object 时不发送 Schema
+        let req = chat_completion::ChatCompletionRequestBuilder::default()
+            .model("ep-20240817170913-w9q57".to_string())
+            .messages(chat_request().messages().to_vec())
+            .response_format(ResponseFormat::JsonObject)
+            .build()
+            .unwrap();
+        let _: City = sdk.chat_completion_typed(&req).await.unwrap();
+        assert!(requests.lock().unwrap()[1].contains(r#""response_format":{"type":"json_object"}"#));
+    }
+
+    #[tokio::test]
+    async fn stream_callback_should_split_reasoning_and_content() {
+        #[derive(Default)]
+        struct Collector {
+            reasoning: Mutex<String>,
+            content: Mutex<String>,
+            ended: Mutex<bool>,
+        }
+        impl MessageEvent for Collector {
+            fn on_message(&self, _chat_completion: &ChatCompletionChunkResponse) {}
+            fn on_end(&self) {
+                *self.ended.lock().unwrap() = true;
+            }
+            fn on_reasoning(&self, _index: usize, reasoning: &str) {
+                self.reasoning.lock().unwrap().push_str(reasoning);
+            }
+            fn on_content(&self, _index: usize, content: &str) {
+                self.content.lock().unwrap().push_str(content);
+            }
+        }
+
+        let chunk = |delta: &str| {
+            format!(
+                "data: {{\"id\":\"0217\",\"model\":\"doubao-1-5-thinking-pro\",\"object\":\"chat.completion.chunk\",\"created\":1,\"choices\":[{{\"index\":0,\"delta\":{}}}]}}\n\n",
+                delta
+            )
+        };
+        let body = [
+            chunk(r#"{"role":"assistant","content":"","reasoning_content":"用户在问"}"#),
+            chunk(r#"{"content":"","reasoning_content":"我是谁"}"#),
+            chunk(r#"{"content":"我是豆包"}"#),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+        let (base_url, _) = mock_server(vec![http_response(200, &[("content-type", "text/event-stream")], &body)]).await;
+        let collector = Collector::default();
+        mock_sdk(&base_url)
+            .chat_completion_stream(&chat_request(), &collector)
+            .await
+            .unwrap();
+        assert_eq!(*collector.reasoning.lock().unwrap(), "用户在问我是谁");
+        assert_eq!(*collector.content.lock().unwrap(), "我是豆包");
+        assert!(*collector.ended.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn chunk_stream_should_return_meta() {
+        let body = concat!(
+            "data: {\"id\":\"0217\",\"model\":\"doubao-pro-32k-240515\",\"object\":\"chat.completion.chunk\",\"created\":1,\"choices\":[{\"index\":0,\"delta\":{\"content\":\"你好\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let headers = [("content-type", "text/event-stream"), ("x-request-id", "0217-stream")];
+        let (base_url, requests) = mock_server(vec![http_response(200, &headers, body)]).await;
+        let options = meta::RequestOptionsBuilder::default()
+            .client_request_id("trace-stream")
+            .build()
+            .unwrap();
+        let res = mock_sdk(&base_url)
+            .chat_completion_chunk_stream_with_meta(&chat_request(), &options)
+            .await
+            .unwrap();
+        assert_eq!(res.meta.request_id.as_deref(), Some("0217-stream"));
+        assert_eq!(res.meta.client_request_id.as_deref(), Some("trace-stream"));
+        let chunks: Vec<_> = res.into_inner().collect().await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].as_ref().unwrap().delta_text(), Some("你好"));
+        assert!(requests.lock().unwrap()[0].contains("x-client-request-id: trace-stream"));
+    }
+
+    #[tokio::test]
+    async fn api_error_from_mock_server_should_be_typed() {
+        let body = r#"{"error":{"code":"AuthenticationError","message":"the API key is invalid","type":"Unauthorized"}}"#;
+        let (base_url, _) =
+            mock_server(vec![http_response(401, &[("x-request-id", "0217-bad-key")], body)]).await;
+        let err = mock_sdk(&base_url).chat_completion(&chat_request()).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+        assert_eq!(err.ark_error().unwrap().code, "AuthenticationError");
+        assert_eq!(err.request_id(), Some("0217-bad-key"));
+    }
+
+    #[tokio::test]
+    async fn transient_errors_should_be_retried() {
+        let (base_url, requests) = mock_server(vec![
+            http_response(503, &[], "service unavailable"),
+            http_response(429, &[("retry-after", "0")], r#"{"error":{"code":"RateLimitExceeded","message":"slow down"}}"#),
+            http_response(200, &[], CHAT_COMPLETION_RESPONSE),
+        ])
+        .await;
+        let res = mock_sdk(&base_url).chat_completion(&chat_request()).await.unwrap();
+        assert_eq!(res.id, "021718067849899d92fcbe0865fdffdde");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_should_stop_at_max_attempts() {
+        let (base_url, requests) = mock_server(vec![http_response(500, &[], "oops")]).await;
+        let err = mock_sdk(&base_url).chat_completion(&chat_request()).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn vision_tokens_should_be_refunded_on_error() {
+        let body = r#"{"error":{"code":"InvalidParameter","message":"image is invalid"}}"#;
+        let (base_url, _) = mock_server(vec![http_response(400, &[], body)]).await;
+        let limiter = Arc::new(RateLimiter::new(RateLimits::default()).with_model(
+            "ep-vision",
+            limiter::RateLimitsBuilder::default().tokens_per_minute(2000).build().unwrap(),
+        ));
+        let mut sdk = mock_sdk(&base_url);
+        sdk.limiter = Some(limiter.clone());
+        let req = vision_pro::VisionProRequestBuilder::default()
+            .model("ep-vision".to_string())
+            .messages(vec![vision_pro::VisionProMessage::User(vision_pro::UserMessage {
+                content: vec![
+                    vision_pro::Content::text("图里有什么"),
+                    vision_pro::Content::image_url("https://example.com/cat.png"),
+                ],
+            })])
+            .max_tokens(100)
+            .build()
+            .unwrap();
+        assert_eq!(req.estimate_tokens(), 5 + limiter::ESTIMATED_IMAGE_TOKENS + 4 + 100);
+
+        assert!(sdk.vision_pro(&req).await.is_err());
+        // 预估的 token 已经退回，整个桶可以立即用完
+        let permit = tokio::time::timeout(Duration::from_millis(100), limiter.acquire("ep-vision", 2000)).await;
+        assert!(permit.is_ok());
+    }
+
+    #[test]
+    fn sdk_builder_should_apply_http_config() {
+        let sdk = LlmSdkBuilder::default()
+            .key("test-key")
+            .connect_timeout(Duration::from_secs(3))
+            .timeout(Duration::from_secs(30))
+            .pool_max_idle_per_host(8)
+            .user_agent("llm-sdk-test")
+            .build()
+            .unwrap();
+        assert_eq!(sdk.http_config().connect_timeout, Duration::from_secs(3));
+        assert_eq!(sdk.http_config().timeout, Duration::from_secs(30));
+        assert_eq!(sdk.http_config().pool_max_idle_per_host, Some(8));
+
+        let sdk = LlmSdkBuilder::default()
+            .key("test-key")
+            .client(Client::new())
+            .build()
+            .unwrap();
+        assert_eq!(sdk.http_config().timeout, Duration::from_secs(config::DEFAULT_TIMEOUT));
+        assert_eq!(sdk.base_url().as_str(), "https://ark.cn-beijing.volces.com/api/v3/");
+    }
+
+    #[test]
+    fn sdk_debug_should_not_leak_key() {
+        let sdk = LlmSdkBuilder::default().key("sk-secret-key").build().unwrap();
+        let debug = format!("{:?}", sdk);
+        assert!(!debug.contains("sk-secret-key"));
+        assert!(debug.contains("SecretString(***)"));
+    }
+
+    #[test]
+    fn sdk_builder_should_reject_bad_config() {
+        assert!(matches!(
+            LlmSdkBuilder::default().build(),
+            Err(LlmSdkBuilderError::UninitializedField("key"))
+        ));
+        assert!(matches!(
+            LlmSdkBuilder::default().key("test-key").proxy("::not a proxy::").build(),
+            Err(LlmSdkBuilderError::ValidationError(_))
+        ));
+        assert!(matches!(
+            LlmSdkBuilder::default().key("test-key").base_url("/api/v3/chat/completions").build(),
+            Err(LlmSdkBuilderError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn sdk_builder_should_support_regions() {
+        let sdk = LlmSdkBuilder::default()
+            .key("test-key")
+            .region(Region::CnShanghai)
+            .build()
+            .unwrap();
+        assert_eq!(
+            sdk.endpoint("chat/completions").unwrap().as_str(),
+            "https://ark.cn-shanghai.volces.com/api/v3/chat/completions"
+        );
+        let sdk = LlmSdkBuilder::default()
+            .key("test-key")
+            .base_url("http://127.0.0.1:8080/api/v3")
+            .build()
+            .unwrap();
+        assert_eq!(
+            sdk.endpoint("embeddings").unwrap().as_str(),
+            "http://127.0.0.1:8080/api/v3/embeddings"
+        );
+    }
 }
 
 
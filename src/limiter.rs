@@ -0,0 +1,277 @@
+use derive_builder::Builder;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// 单个推理接入点（model）的限流配置，不设置的项不做限制
+#[derive(Debug, Clone, Default, Builder)]
+pub struct RateLimits {
+    /// 每分钟请求数（RPM）
+    #[builder(default, setter(strip_option))]
+    pub requests_per_minute: Option<u32>,
+    /// 每分钟 token 数（TPM）
+    #[builder(default, setter(strip_option))]
+    pub tokens_per_minute: Option<u32>,
+    /// 同时进行中的请求数上限
+    #[builder(default, setter(strip_option))]
+    pub max_in_flight: Option<usize>,
+}
+
+/// 客户端限流器，按 model 分别计数。
+/// 请求按到达顺序排队，RPM/TPM 用令牌桶实现，调用前按估算的 token 数扣减，调用后按 Usage 修正。
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// 没有单独配置的 model 使用的限制
+    default_limits: RateLimits,
+    limits: HashMap<String, RateLimits>,
+    models: Mutex<HashMap<String, Arc<ModelLimiter>>>,
+}
+
+impl RateLimiter {
+    pub fn new(default_limits: RateLimits) -> Self {
+        Self {
+            default_limits,
+            ..Default::default()
+        }
+    }
+
+    /// 给指定 model 单独配置限制
+    pub fn with_model(mut self, model: impl Into<String>, limits: RateLimits) -> Self {
+        self.limits.insert(model.into(), limits);
+        self
+    }
+
+    /// 等待直到允许发出请求，estimated_tokens 为预估的 token 消耗
+    pub async fn acquire(&self, model: &str, estimated_tokens: u32) -> RatePermit {
+        let limiter = self.model_limiter(model);
+        let in_flight = match &limiter.in_flight {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("rate limiter semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let charged = limiter.take(estimated_tokens).await;
+        RatePermit {
+            limiter,
+            charged,
+            settled: AtomicBool::new(false),
+            _in_flight: in_flight,
+        }
+    }
+
+    fn model_limiter(&self, model: &str) -> Arc<ModelLimiter> {
+        let mut models = self.models.lock().unwrap();
+        models
+            .entry(model.to_string())
+            .or_insert_with(|| {
+                let limits = self.limits.get(model).unwrap_or(&self.default_limits);
+                Arc::new(ModelLimiter::new(limits))
+            })
+            .clone()
+    }
+}
+
+/// 一次请求的许可，drop 时释放并发名额
+#[derive(Debug)]
+pub struct RatePermit {
+    limiter: Arc<ModelLimiter>,
+    charged: u32,
+    /// 只有第一次 settle 生效，流式响应中可能有多个块带 usage
+    settled: AtomicBool,
+    _in_flight: Option<OwnedSemaphorePermit>,
+}
+
+impl RatePermit {
+    /// 用实际消耗的 token 数修正预估值，重复调用时只有第一次生效
+    pub fn settle(&self, actual_tokens: u32) {
+        if self.settled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let mut buckets = self.limiter.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.tokens.as_mut() {
+            bucket.refill(Instant::now());
+            bucket.available += self.charged as f64 - actual_tokens as f64;
+            bucket.available = bucket.available.min(bucket.capacity);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ModelLimiter {
+    in_flight: Option<Arc<Semaphore>>,
+    /// tokio 的 Mutex 是公平锁，拿到锁的顺序就是排队的顺序
+    queue: tokio::sync::Mutex<()>,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+impl ModelLimiter {
+    fn new(limits: &RateLimits) -> Self {
+        let now = Instant::now();
+        Self {
+            in_flight: limits.max_in_flight.map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            queue: tokio::sync::Mutex::new(()),
+            buckets: Mutex::new(Buckets {
+                requests: limits.requests_per_minute.map(|rpm| TokenBucket::per_minute(rpm, now)),
+                tokens: limits.tokens_per_minute.map(|tpm| TokenBucket::per_minute(tpm, now)),
+            }),
+        }
+    }
+
+    /// 排队扣减令牌，返回实际扣掉的 token 数
+    async fn take(&self, estimated_tokens: u32) -> u32 {
+        let _turn = self.queue.lock().await;
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = Instant::now();
+                // 预估值超过桶容量时按容量扣，避免永远等不到
+                let charged = match &buckets.tokens {
+                    Some(bucket) => (estimated_tokens as f64).min(bucket.capacity),
+                    None => estimated_tokens as f64,
+                };
+                let request_wait = buckets
+                    .requests
+                    .as_mut()
+                    .map_or(Duration::ZERO, |b| b.wait_for(1.0, now));
+                let token_wait = buckets
+                    .tokens
+                    .as_mut()
+                    .map_or(Duration::ZERO, |b| b.wait_for(charged, now));
+                let wait = request_wait.max(token_wait);
+                if wait.is_zero() {
+                    if let Some(bucket) = buckets.requests.as_mut() {
+                        bucket.available -= 1.0;
+                    }
+                    if let Some(bucket) = buckets.tokens.as_mut() {
+                        bucket.available -= charged;
+                    }
+                    return charged as u32;
+                }
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    per_second: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(limit: u32, now: Instant) -> Self {
+        let capacity = limit.max(1) as f64;
+        Self {
+            capacity,
+            available: capacity,
+            per_second: capacity / 60.0,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// 还需要等待多久才能扣出 amount 个令牌
+    fn wait_for(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        if self.available >= amount {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.available) / self.per_second)
+        }
+    }
+}
+
+/// 客户端限流时每张图片按这么多 token 预估，实际用量以响应中的 usage 为准
+pub(crate) const ESTIMATED_IMAGE_TOKENS: u32 = 1000;
+
+/// 粗略估算文本的 token 数：中日韩字符按 1 个 token 计，其余按 4 个字节 1 个 token 计
+pub fn estimate_tokens(text: &str) -> u32 {
+    let mut cjk = 0u32;
+    let mut other_bytes = 0u32;
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+        } else {
+            other_bytes += c.len_utf8() as u32;
+        }
+    }
+    cjk + other_bytes.div_ceil(4)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_tokens_should_work() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("hello world!"), 3);
+        assert_eq!(estimate_tokens("你是谁"), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_per_minute_should_be_limited() {
+        let limiter = RateLimiter::new(RateLimitsBuilder::default().requests_per_minute(2).build().unwrap());
+        let start = tokio::time::Instant::now();
+        limiter.acquire("ep-1", 0).await;
+        limiter.acquire("ep-1", 0).await;
+        // 其他 model 不受影响
+        limiter.acquire("ep-2", 0).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        limiter.acquire("ep-1", 0).await;
+        assert!(start.elapsed() >= Duration::from_secs(29));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_should_be_corrected_by_usage() {
+        let limiter = RateLimiter::new(RateLimits::default())
+            .with_model("ep-1", RateLimitsBuilder::default().tokens_per_minute(600).build().unwrap());
+        let start = tokio::time::Instant::now();
+        let permit = limiter.acquire("ep-1", 600).await;
+        // 实际只用了 100，剩余的 500 退回，重复 settle 不会再退
+        permit.settle(100);
+        permit.settle(0);
+        limiter.acquire("ep-1", 500).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        limiter.acquire("ep-1", 60).await;
+        assert!(start.elapsed() >= Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn in_flight_should_be_capped() {
+        let limiter = RateLimiter::new(RateLimitsBuilder::default().max_in_flight(1).build().unwrap());
+        let first = limiter.acquire("ep-1", 0).await;
+        let second = tokio::time::timeout(Duration::from_millis(20), limiter.acquire("ep-1", 0)).await;
+        assert!(second.is_err());
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_millis(20), limiter.acquire("ep-1", 0)).await;
+        assert!(second.is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+use derive_builder::Builder;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::ops::Deref;
+use std::time::{Duration, SystemTime};
+
+/// 方舟返回的请求 ID
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// 调用方自定义的请求 ID，服务端会原样返回
+pub const CLIENT_REQUEST_ID_HEADER: &str = "x-client-request-id";
+
+/// 单次调用的可选参数
+#[derive(Debug, Clone, Default, Builder)]
+pub struct RequestOptions {
+    /// 自定义请求 ID，通过 X-Client-Request-Id 请求头发送，便于和自己的日志关联
+    #[builder(default, setter(into, strip_option))]
+    pub client_request_id: Option<String>,
+}
+
+/// HTTP 响应的元信息
+#[derive(Debug, Clone, Default)]
+pub struct ResponseMeta {
+    /// HTTP 状态码
+    pub status: StatusCode,
+    /// 方舟返回的请求 ID，排查问题时提供给方舟
+    pub request_id: Option<String>,
+    /// 调用方自定义的请求 ID
+    pub client_request_id: Option<String>,
+    /// 从发出请求到读完响应体的耗时，包含重试，不包含限流排队
+    pub latency: Duration,
+    /// 限流相关的响应头
+    pub rate_limit: RateLimitInfo,
+}
+
+/// x-ratelimit-* 响应头
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    /// 每分钟请求数上限
+    pub limit_requests: Option<u64>,
+    /// 每分钟 token 数上限
+    pub limit_tokens: Option<u64>,
+    /// 剩余请求数
+    pub remaining_requests: Option<u64>,
+    /// 剩余 token 数
+    pub remaining_tokens: Option<u64>,
+    /// 请求数额度重置时间，比如 1s、6m0s
+    pub reset_requests: Option<String>,
+    /// token 额度重置时间
+    pub reset_tokens: Option<String>,
+}
+
+/// 带响应元信息的返回值，可以直接当作 T 使用
+#[derive(Debug, Clone)]
+pub struct WithMeta<T> {
+    pub data: T,
+    pub meta: ResponseMeta,
+}
+
+impl<T> WithMeta<T> {
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T> Deref for WithMeta<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl ResponseMeta {
+    pub(crate) fn from_headers(status: StatusCode, headers: &HeaderMap, latency: Duration) -> Self {
+        Self {
+            status,
+            request_id: header_str(headers, REQUEST_ID_HEADER),
+            client_request_id: header_str(headers, CLIENT_REQUEST_ID_HEADER),
+            latency,
+            rate_limit: RateLimitInfo {
+                limit_requests: header_u64(headers, "x-ratelimit-limit-requests"),
+                limit_tokens: header_u64(headers, "x-ratelimit-limit-tokens"),
+                remaining_requests: header_u64(headers, "x-ratelimit-remaining-requests"),
+                remaining_tokens: header_u64(headers, "x-ratelimit-remaining-tokens"),
+                reset_requests: header_str(headers, "x-ratelimit-reset-requests"),
+                reset_tokens: header_str(headers, "x-ratelimit-reset-tokens"),
+            },
+        }
+    }
+}
+
+pub(crate) fn header_str(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    header_str(headers, name)?.parse().ok()
+}
+
+/// 解析 Retry-After 响应头，支持秒数和 HTTP 日期两种格式
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn response_meta_should_parse_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("0217296714957893c183a1fb82c613f4d"));
+        headers.insert(CLIENT_REQUEST_ID_HEADER, HeaderValue::from_static("my-trace-1"));
+        headers.insert("x-ratelimit-remaining-requests", HeaderValue::from_static("99"));
+        headers.insert("x-ratelimit-remaining-tokens", HeaderValue::from_static("not-a-number"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("6m0s"));
+        let meta = ResponseMeta::from_headers(StatusCode::OK, &headers, Duration::from_millis(12));
+        assert_eq!(meta.request_id.as_deref(), Some("0217296714957893c183a1fb82c613f4d"));
+        assert_eq!(meta.client_request_id.as_deref(), Some("my-trace-1"));
+        assert_eq!(meta.rate_limit.remaining_requests, Some(99));
+        assert_eq!(meta.rate_limit.remaining_tokens, None);
+        assert_eq!(meta.rate_limit.reset_tokens.as_deref(), Some("6m0s"));
+    }
+
+    #[test]
+    fn retry_after_header_should_parse() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("12"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(12)));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+}
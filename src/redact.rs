@@ -0,0 +1,142 @@
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+/// 需要脱敏的 JSON 字段：消息内容、图片地址、工具参数和向量化输入
+const SENSITIVE_FIELDS: &[&str] = &["content", "text", "url", "arguments", "input", "reasoning_content"];
+
+/// API Key 之类的敏感字符串，Debug 输出时隐藏内容
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// 取出原始内容，只在真正需要时调用，比如设置 Authorization 请求头
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+/// 日志中输出请求和响应内容前，对消息内容做脱敏处理。
+/// 默认只保留长度，可以换成自己的实现，比如只打码手机号。
+#[derive(Clone)]
+pub struct Redactor(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl Redactor {
+    pub fn new(redact: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self(Arc::new(redact))
+    }
+
+    /// 原样输出，仅用于本地调试
+    pub fn keep() -> Self {
+        Self::new(|text| text.to_string())
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        (self.0)(text)
+    }
+
+    /// 对 JSON 中的敏感字段逐个脱敏，其他字段（model、usage 等）保持不变
+    pub fn redact_json(&self, value: &Value) -> Value {
+        let mut value = value.clone();
+        self.redact_value(&mut value, false);
+        value
+    }
+
+    /// 对 JSON 文本脱敏，不是合法 JSON 时整体当作消息内容处理
+    pub fn redact_json_str(&self, text: &str) -> String {
+        match serde_json::from_str::<Value>(text) {
+            Ok(value) => self.redact_json(&value).to_string(),
+            Err(_) => self.redact(text),
+        }
+    }
+
+    fn redact_value(&self, value: &mut Value, sensitive: bool) {
+        match value {
+            Value::String(text) if sensitive => *text = self.redact(text),
+            Value::Array(items) => {
+                for item in items {
+                    self.redact_value(item, sensitive);
+                }
+            }
+            // 进入对象后按字段名重新判断，content 数组里的 type 之类的字段不做处理
+            Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    let sensitive = SENSITIVE_FIELDS.contains(&name.as_str());
+                    self.redact_value(field, sensitive);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new(|text| format!("[{} chars redacted]", text.chars().count()))
+    }
+}
+
+impl fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Redactor")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secret_string_debug_should_be_redacted() {
+        let key = SecretString::from("sk-1234567890");
+        assert_eq!(format!("{:?}", key), "SecretString(***)");
+        assert_eq!(key.expose_secret(), "sk-1234567890");
+    }
+
+    #[test]
+    fn redactor_should_only_touch_message_contents() {
+        let body = json!({
+            "model": "ep-20240817170913-w9q57",
+            "messages": [
+                {"role": "system", "content": "你是豆包"},
+                {"role": "user", "content": [{"type": "text", "text": "图中是什么?"}, {"type": "image_url", "image_url": {"url": "https://example.com/a.jpeg"}}]}
+            ],
+            "input": ["花椰菜又称菜花"]
+        });
+        let redacted = Redactor::default().redact_json(&body);
+        assert_eq!(redacted["model"], "ep-20240817170913-w9q57");
+        assert_eq!(redacted["messages"][0]["role"], "system");
+        assert_eq!(redacted["messages"][0]["content"], "[4 chars redacted]");
+        assert_eq!(redacted["messages"][1]["content"][0]["type"], "text");
+        assert_eq!(redacted["messages"][1]["content"][0]["text"], "[6 chars redacted]");
+        assert_eq!(redacted["messages"][1]["content"][1]["image_url"]["url"], "[26 chars redacted]");
+        assert_eq!(redacted["input"][0], "[7 chars redacted]");
+
+        let upper = Redactor::new(|text| text.to_uppercase());
+        assert_eq!(upper.redact_json_str(r#"{"id":"a","content":"hi"}"#), r#"{"content":"HI","id":"a"}"#);
+        assert_eq!(Redactor::keep().redact_json_str("not json"), "not json");
+    }
+}
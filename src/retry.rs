@@ -0,0 +1,128 @@
+use derive_builder::Builder;
+use std::time::Duration;
+
+use crate::error::LlmError;
+
+/// 失败重试策略，对 chat_completion、vision_lite、vision_pro、embeddings 生效。
+/// 流式请求只在拿到 2xx 响应之前重试，开始向调用方输出数据之后不会再重新发送。
+#[derive(Debug, Clone, Builder)]
+pub struct RetryPolicy {
+    /// 最多尝试的次数（包含第一次请求），1 表示不重试。
+    /// chat 等接口不是幂等的，请求已经到达服务端后才出现的网络错误或 5xx 也会重试，可能重复计费；
+    /// 不能接受时设为 1，或者使用 `RetryPolicy::none()`
+    #[builder(default = "3")]
+    pub max_attempts: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    #[builder(default = "Duration::from_millis(500)")]
+    pub base_delay: Duration,
+    /// 单次等待时间的上限，服务端给出的 Retry-After 也不会超过它
+    #[builder(default = "Duration::from_secs(30)")]
+    pub max_delay: Duration,
+    /// 是否在等待时间上加随机抖动，避免大量请求同时重试
+    #[builder(default = "true")]
+    pub jitter: bool,
+    /// 需要重试的 HTTP 状态码
+    #[builder(default = "vec![408, 429, 500, 502, 503, 504]")]
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicyBuilder::default().build().unwrap()
+    }
+}
+
+impl RetryPolicy {
+    /// 不重试
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// 该错误是否需要重试
+    pub fn should_retry(&self, err: &LlmError) -> bool {
+        match err {
+            LlmError::Transport(e) => !e.is_builder() && !e.is_redirect(),
+            LlmError::Timeout(_) => true,
+            LlmError::Api { status, .. } | LlmError::ContentFilter { status, .. } => {
+                self.retryable_statuses.contains(&status.as_u16())
+            }
+            LlmError::RateLimited { .. } => self.retryable_statuses.contains(&429),
+            _ => false,
+        }
+    }
+
+    /// 第 attempt 次请求失败后需要等待的时间。
+    /// 429 和 503 响应给了 Retry-After 时以它为准，但不超过 max_delay
+    pub fn delay(&self, attempt: u32, err: &LlmError) -> Duration {
+        if let Some(retry_after) = err.retry_after() {
+            return retry_after.min(self.max_delay);
+        }
+        let exp = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .base_delay
+            .saturating_mul(1u32 << exp)
+            .min(self.max_delay);
+        if self.jitter {
+            // 在 [backoff/2, backoff] 之间随机
+            let half = backoff / 2;
+            half + half.mul_f64(fastrand::f64())
+        } else {
+            backoff
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn backoff_should_grow_and_cap() {
+        let policy = RetryPolicyBuilder::default()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350))
+            .jitter(false)
+            .build()
+            .unwrap();
+        let err = LlmError::from_response(StatusCode::SERVICE_UNAVAILABLE, None, "");
+        assert!(policy.should_retry(&err));
+        assert_eq!(policy.delay(1, &err), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, &err), Duration::from_millis(200));
+        assert_eq!(policy.delay(3, &err), Duration::from_millis(350));
+        assert_eq!(policy.delay(40, &err), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn retry_after_should_win() {
+        let policy = RetryPolicy::default();
+        let err = LlmError::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(7)),
+            "",
+        );
+        assert!(policy.should_retry(&err));
+        assert_eq!(policy.delay(1, &err), Duration::from_secs(7));
+
+        // 503 同样遵守 Retry-After，过长的等待时间截断到 max_delay
+        let err = LlmError::from_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Some(Duration::from_secs(3600)),
+            "",
+        );
+        assert!(policy.should_retry(&err));
+        assert_eq!(policy.delay(1, &err), policy.max_delay);
+    }
+
+    #[test]
+    fn client_errors_should_not_retry() {
+        let policy = RetryPolicy::default();
+        let err = LlmError::from_response(StatusCode::BAD_REQUEST, None, "");
+        assert!(!policy.should_retry(&err));
+        let jittered = policy.delay(1, &err);
+        assert!(jittered >= Duration::from_millis(250) && jittered <= Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,158 @@
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+#[cfg(feature = "schema")]
+use crate::api::chat_completion::{FunctionDefinition, ResponseFormat};
+
+/// 解析模型生成的工具参数失败
+#[derive(Debug, Error)]
+pub enum ArgumentsError {
+    /// 不是合法的 JSON
+    #[error("arguments are not valid JSON: {0}")]
+    Syntax(#[source] serde_json::Error),
+    /// 模型虚构了参数定义中没有的字段，需要在参数类型上加 `#[serde(deny_unknown_fields)]`
+    #[error("unknown field `{field}` in arguments")]
+    UnknownField {
+        field: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// 缺少必填字段
+    #[error("missing field `{field}` in arguments")]
+    MissingField {
+        field: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// 字段类型或取值不对
+    #[error("invalid arguments: {0}")]
+    Invalid(#[source] serde_json::Error),
+}
+
+/// 把 Function::arguments 解析成 T，没有参数时模型可能返回空字符串，按 `{}` 处理
+pub fn parse_arguments<T: DeserializeOwned>(arguments: &str) -> Result<T, ArgumentsError> {
+    let arguments = if arguments.trim().is_empty() { "{}" } else { arguments };
+    serde_json::from_str(arguments).map_err(|e| {
+        if e.is_syntax() || e.is_eof() {
+            return ArgumentsError::Syntax(e);
+        }
+        // serde 没有提供结构化的字段错误，只能匹配错误信息，比如 unknown field `city`, expected `location`。
+        // 信息格式由 serde_error_messages_should_be_pinned 测试固定，升级 serde_json 后格式变化时测试会失败
+        let message = e.to_string();
+        if let Some(field) = quoted_field(&message, "unknown field `") {
+            ArgumentsError::UnknownField { field, source: e }
+        } else if let Some(field) = quoted_field(&message, "missing field `") {
+            ArgumentsError::MissingField { field, source: e }
+        } else {
+            ArgumentsError::Invalid(e)
+        }
+    })
+}
+
+fn quoted_field(message: &str, prefix: &str) -> Option<String> {
+    let rest = &message[message.find(prefix)? + prefix.len()..];
+    Some(rest[..rest.find('`')?].to_string())
+}
+
+/// 生成 T 的 JSON Schema，子类型直接内联，去掉 $schema 和 title
+#[cfg(feature = "schema")]
+pub fn json_schema_for<T: schemars::JsonSchema>() -> serde_json::Value {
+    let mut schema = schemars::generate::SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>();
+    schema.remove("$schema");
+    schema.remove("title");
+    schema.to_value()
+}
+
+#[cfg(feature = "schema")]
+impl FunctionDefinition {
+    /// 用参数类型 T 生成函数声明，解析 arguments 时使用同一个类型，避免手写的 Schema 和代码不一致
+    pub fn from_type<T: schemars::JsonSchema>(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: Some(description.into()),
+            parameters: Some(json_schema_for::<T>()),
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+impl ResponseFormat {
+    /// 用输出类型 T 生成 json_schema 格式
+    pub fn from_type<T: schemars::JsonSchema>(name: impl Into<String>) -> Self {
+        ResponseFormat::json_schema(name, json_schema_for::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+    #[serde(deny_unknown_fields)]
+    struct WeatherArgs {
+        /// 城市名称，比如北京
+        location: String,
+        /// 温度单位
+        #[serde(default)]
+        unit: Option<String>,
+    }
+
+    #[test]
+    fn arguments_should_be_validated() {
+        let args: WeatherArgs = parse_arguments(r#"{"location":"北京"}"#).unwrap();
+        assert_eq!(args.location, "北京");
+        assert!(args.unit.is_none());
+
+        let err = parse_arguments::<WeatherArgs>(r#"{"location":"北京","date":"today"}"#).unwrap_err();
+        assert!(matches!(err, ArgumentsError::UnknownField { ref field, .. } if field == "date"));
+        let err = parse_arguments::<WeatherArgs>("").unwrap_err();
+        assert!(matches!(err, ArgumentsError::MissingField { ref field, .. } if field == "location"));
+        let err = parse_arguments::<WeatherArgs>(r#"{"location":"北京""#).unwrap_err();
+        assert!(matches!(err, ArgumentsError::Syntax(_)));
+        let err = parse_arguments::<WeatherArgs>(r#"{"location":1}"#).unwrap_err();
+        assert!(matches!(err, ArgumentsError::Invalid(_)));
+    }
+
+    #[test]
+    fn serde_error_messages_should_be_pinned() {
+        let err = serde_json::from_str::<WeatherArgs>(r#"{"location":"北京","date":"today"}"#).unwrap_err();
+        assert!(
+            err.to_string().starts_with("unknown field `date`"),
+            "serde_json changed its unknown field message: {}",
+            err
+        );
+        assert!(matches!(
+            parse_arguments::<WeatherArgs>(r#"{"location":"北京","date":"today"}"#),
+            Err(ArgumentsError::UnknownField { ref field, .. }) if field == "date"
+        ));
+
+        let err = serde_json::from_str::<WeatherArgs>(r#"{"unit":"celsius"}"#).unwrap_err();
+        assert!(
+            err.to_string().starts_with("missing field `location`"),
+            "serde_json changed its missing field message: {}",
+            err
+        );
+        assert!(matches!(
+            parse_arguments::<WeatherArgs>(r#"{"unit":"celsius"}"#),
+            Err(ArgumentsError::MissingField { ref field, .. }) if field == "location"
+        ));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn function_definition_should_be_derived_from_type() {
+        let definition = FunctionDefinition::from_type::<WeatherArgs>("get_current_weather", "获取指定城市的天气");
+        let parameters = definition.parameters.unwrap();
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["properties"]["location"]["type"], "string");
+        assert_eq!(parameters["properties"]["location"]["description"], "城市名称，比如北京");
+        assert_eq!(parameters["required"], serde_json::json!(["location"]));
+        assert_eq!(parameters["additionalProperties"], false);
+        assert!(parameters.get("$schema").is_none());
+    }
+}
@@ -0,0 +1,330 @@
+//! Server-Sent Events 增量解码
+//! https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use tracing::trace;
+
+use crate::error::{ArkErrorBody, LlmError, Result};
+use crate::redact::Redactor;
+
+/// 流式响应结束标记，对应 `data: [DONE]`
+pub const DONE: &str = "[DONE]";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// event 字段，未指定时为 None（即默认的 message 事件）
+    pub event: Option<String>,
+    /// 所有 data 行，按 `\n` 拼接
+    pub data: String,
+    /// 最近一次收到的 id 字段
+    pub id: Option<String>,
+    /// retry 字段，服务端建议的重连间隔（毫秒）
+    pub retry: Option<u64>,
+}
+
+impl SseEvent {
+    /// 是否为 `data: [DONE]` 结束事件
+    pub fn is_done(&self) -> bool {
+        self.data == DONE
+    }
+}
+
+/// 按字节增量解析 SSE 流，一个 chunk 可以包含多个事件，一个事件也可以跨多个 chunk。
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buf: Vec<u8>,
+    /// 上一个 chunk 以 `\r` 结尾时，需要吞掉下一个 chunk 开头的 `\n`
+    skip_lf: bool,
+    event: Option<String>,
+    data: Vec<String>,
+    has_data: bool,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一段字节，返回其中完整的事件
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut chunk = chunk;
+        if self.skip_lf {
+            if let Some(rest) = chunk.strip_prefix(b"\n") {
+                chunk = rest;
+            }
+            if !chunk.is_empty() {
+                self.skip_lf = false;
+            }
+        }
+        self.buf.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buf.len() {
+            match self.buf[i] {
+                b'\n' => {
+                    let line = self.buf[start..i].to_vec();
+                    self.process_line(&line, &mut events);
+                    i += 1;
+                    start = i;
+                }
+                b'\r' => {
+                    let line = self.buf[start..i].to_vec();
+                    self.process_line(&line, &mut events);
+                    i += 1;
+                    if i == self.buf.len() {
+                        self.skip_lf = true;
+                    } else if self.buf[i] == b'\n' {
+                        i += 1;
+                    }
+                    start = i;
+                }
+                _ => i += 1,
+            }
+        }
+        self.buf.drain(..start);
+        events
+    }
+
+    /// 流结束时调用，把没有以空行结尾的最后一个事件吐出来
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            let mut events = Vec::new();
+            self.process_line(&line, &mut events);
+        }
+        self.skip_lf = false;
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &[u8], events: &mut Vec<SseEvent>) {
+        if line.is_empty() {
+            if let Some(event) = self.dispatch() {
+                events.push(event);
+            }
+            return;
+        }
+        // 注释行
+        if line[0] == b':' {
+            return;
+        }
+        let line = String::from_utf8_lossy(line);
+        let (field, value) = match line.find(':') {
+            Some(pos) => {
+                let value = &line[pos + 1..];
+                (&line[..pos], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push(value.to_string());
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        if !self.has_data {
+            self.data.clear();
+            return None;
+        }
+        self.has_data = false;
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent {
+            event,
+            data,
+            id: self.last_id.clone(),
+            retry,
+        })
+    }
+}
+
+/// 把 HTTP 响应体转换成按事件反序列化的 Stream，遇到 `[DONE]`、出错或者连接关闭时结束。
+/// 每个事件的内容脱敏后以 trace 级别输出。
+pub(crate) fn json_stream<T, S, E>(body: S, redactor: Redactor) -> impl Stream<Item = Result<T>> + Send + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: Into<LlmError>,
+{
+    let state = JsonStreamState {
+        body: Box::pin(body),
+        decoder: SseDecoder::new(),
+        pending: VecDeque::new(),
+        finished: false,
+        truncated: false,
+        redactor,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(sse_event) = state.pending.pop_front() {
+                if sse_event.is_done() {
+                    return None;
+                }
+                trace!("event: {}", state.redactor.redact_json_str(&sse_event.data));
+                let item = parse_event(&sse_event);
+                if item.is_err() {
+                    state.finished = true;
+                    state.truncated = false;
+                    state.pending.clear();
+                }
+                return Some((item, state));
+            }
+            if state.finished {
+                // 连接在 [DONE] 之前关闭，先交出 finish 刷新出来的事件，再报告流被截断
+                if std::mem::take(&mut state.truncated) {
+                    let e = LlmError::Stream("stream closed before [DONE]".to_string());
+                    return Some((Err(e), state));
+                }
+                return None;
+            }
+            match state.body.next().await {
+                Some(Ok(chunk)) => {
+                    trace!("received {} bytes", chunk.len());
+                    let events = state.decoder.feed(&chunk);
+                    state.pending.extend(events);
+                }
+                Some(Err(e)) => {
+                    state.finished = true;
+                    state.pending.clear();
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    state.finished = true;
+                    let event = state.decoder.finish();
+                    state.truncated = !event.as_ref().is_some_and(SseEvent::is_done);
+                    state.pending.extend(event);
+                }
+            }
+        }
+    })
+}
+
+fn parse_event<T: DeserializeOwned>(sse_event: &SseEvent) -> Result<T> {
+    serde_json::from_str(&sse_event.data).map_err(|e| {
+        // 流中间返回的错误，格式为 {"error": {...}}
+        match serde_json::from_str::<ArkErrorBody>(&sse_event.data) {
+            Ok(body) => LlmError::Stream(body.error.to_string()),
+            Err(_) => LlmError::deserialize(e, sse_event.data.as_str()),
+        }
+    })
+}
+
+struct JsonStreamState<S> {
+    body: std::pin::Pin<Box<S>>,
+    decoder: SseDecoder,
+    pending: VecDeque<SseEvent>,
+    finished: bool,
+    /// 连接关闭时没有收到 [DONE]
+    truncated: bool,
+    redactor: Redactor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_events_in_one_chunk_should_work() {
+        let mut decoder = SseDecoder::new();
+        let events = decoder.feed(b"data: {\"a\":1}\n\ndata: {\"a\":2}\n\ndata: [DONE]\n\n");
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].data, r#"{"a":1}"#);
+        assert_eq!(events[1].data, r#"{"a":2}"#);
+        assert!(events[2].is_done());
+    }
+
+    #[test]
+    fn event_split_across_chunks_should_work() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.feed(b"da").is_empty());
+        assert!(decoder.feed(b"ta: {\"a\"").is_empty());
+        assert!(decoder.feed(b":1}\r").is_empty());
+        let events = decoder.feed(b"\n\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn fields_and_comments_should_work() {
+        let mut decoder = SseDecoder::new();
+        let events = decoder.feed(
+            b": keep-alive\nevent: update\nid: 7\nretry: 3000\ndata: line1\ndata:line2\n\nid\n\n",
+        );
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: Some("update".to_string()),
+                data: "line1\nline2".to_string(),
+                id: Some("7".to_string()),
+                retry: Some(3000),
+            }]
+        );
+    }
+
+    #[test]
+    fn finish_should_flush_last_event() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.feed(b"data: [DONE]").is_empty());
+        assert!(decoder.finish().unwrap().is_done());
+        assert!(decoder.finish().is_none());
+    }
+
+    #[tokio::test]
+    async fn json_stream_should_stop_at_done() {
+        let chunks: Vec<Result<Bytes>> = vec![
+            Ok(Bytes::from_static(b"data: {\"a\":1}\n\nda")),
+            Ok(Bytes::from_static(b"ta: {\"a\":2}\n\ndata: [DONE]\n\n")),
+            Ok(Bytes::from_static(b"data: {\"a\":3}\n\n")),
+        ];
+        let values: Vec<serde_json::Value> = json_stream(stream::iter(chunks), Redactor::default())
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        assert_eq!(values, vec![serde_json::json!({"a": 1}), serde_json::json!({"a": 2})]);
+    }
+
+    #[tokio::test]
+    async fn json_stream_should_report_protocol_errors() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Chunk {
+            #[allow(dead_code)]
+            a: i32,
+        }
+        let chunks: Vec<Result<Bytes>> = vec![Ok(Bytes::from_static(
+            b"data: {\"error\":{\"code\":\"InternalServiceError\",\"message\":\"oops\"}}\n\n",
+        ))];
+        let values: Vec<Result<Chunk>> = json_stream(stream::iter(chunks), Redactor::default()).collect().await;
+        assert_eq!(values.len(), 1);
+        assert!(matches!(&values[0], Err(LlmError::Stream(msg)) if msg.contains("InternalServiceError")));
+
+        let chunks: Vec<Result<Bytes>> = vec![Ok(Bytes::from_static(b"data: {\"a\":1}\n\n"))];
+        let values: Vec<Result<Chunk>> = json_stream(stream::iter(chunks), Redactor::default()).collect().await;
+        assert_eq!(values.len(), 2);
+        assert!(matches!(&values[1], Err(LlmError::Stream(_))));
+
+        // 最后一个事件没有以空行结束，finish 刷新出来后仍然要报告流被截断
+        let chunks: Vec<Result<Bytes>> = vec![Ok(Bytes::from_static(b"data: {\"a\":1}\n\ndata: {\"a\":2}"))];
+        let values: Vec<Result<Chunk>> = json_stream(stream::iter(chunks), Redactor::default()).collect().await;
+        assert_eq!(values.len(), 3);
+        assert!(values[0].is_ok() && values[1].is_ok());
+        assert!(matches!(&values[2], Err(LlmError::Stream(msg)) if msg.contains("[DONE]")));
+    }
+}
@@ -0,0 +1,240 @@
+//! 每次调用对应一个 tracing span，属性名遵循 OpenTelemetry GenAI 语义约定，
+//! 接入 tracing-opentelemetry 后可以直接按 model 统计耗时和 token 用量。
+//! https://opentelemetry.io/docs/specs/semconv/gen-ai/gen-ai-spans/
+
+use reqwest::Url;
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use crate::api::chat_completion::{ChatCompletionChunkResponse, ChatCompletionResponse};
+use crate::api::embeddings::EmbeddingsResponse;
+use crate::api::multimodal_embeddings::MultimodalEmbeddingsResponse;
+use crate::api::vision_lite::VisionLiteResponse;
+use crate::api::vision_pro::VisionProResponse;
+use crate::error::{LlmError, Result};
+use crate::meta::WithMeta;
+
+pub const OPERATION_NAME: &str = "gen_ai.operation.name";
+pub const REQUEST_MODEL: &str = "gen_ai.request.model";
+pub const REQUEST_STREAM: &str = "gen_ai.request.stream";
+pub const RESPONSE_ID: &str = "gen_ai.response.id";
+pub const RESPONSE_MODEL: &str = "gen_ai.response.model";
+pub const RESPONSE_FINISH_REASONS: &str = "gen_ai.response.finish_reasons";
+pub const USAGE_INPUT_TOKENS: &str = "gen_ai.usage.input_tokens";
+pub const USAGE_OUTPUT_TOKENS: &str = "gen_ai.usage.output_tokens";
+pub const USAGE_TOTAL_TOKENS: &str = "gen_ai.usage.total_tokens";
+/// 流式请求从发出到收到第一个数据块的耗时（秒）
+pub const TIME_TO_FIRST_TOKEN: &str = "gen_ai.server.time_to_first_token";
+pub const ERROR_TYPE: &str = "error.type";
+
+/// GenAI 语义约定中的操作类型，vision 走的也是 chat/completions 接口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Chat,
+    Embeddings,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Chat => "chat",
+            Operation::Embeddings => "embeddings",
+        }
+    }
+}
+
+/// 创建一次调用的 span，api 为 SDK 中的方法名，比如 vision_pro
+pub(crate) fn call_span(operation: Operation, api: &'static str, model: &str, stream: bool, base_url: &Url) -> Span {
+    info_span!(
+        "gen_ai",
+        otel.name = %format_args!("{} {}", operation.as_str(), model),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        gen_ai.system = "volcengine",
+        gen_ai.operation.name = operation.as_str(),
+        gen_ai.request.model = model,
+        gen_ai.request.stream = stream,
+        ark.api = api,
+        server.address = base_url.host_str().unwrap_or_default(),
+        gen_ai.response.id = Empty,
+        gen_ai.response.model = Empty,
+        gen_ai.response.finish_reasons = Empty,
+        gen_ai.usage.input_tokens = Empty,
+        gen_ai.usage.output_tokens = Empty,
+        gen_ai.usage.total_tokens = Empty,
+        gen_ai.server.time_to_first_token = Empty,
+        "error.type" = Empty,
+    )
+}
+
+/// 把响应中的 id、model、finish_reason 和 token 用量记录到 span 上
+pub(crate) trait GenAiResponse {
+    fn record(&self, span: &Span);
+}
+
+pub(crate) fn record_result<T: GenAiResponse>(span: &Span, result: &Result<WithMeta<T>>) {
+    match result {
+        Ok(res) => res.data.record(span),
+        Err(e) => record_error(span, e),
+    }
+}
+
+pub(crate) fn record_error(span: &Span, err: &LlmError) {
+    span.record(ERROR_TYPE, err.kind());
+    span.record("otel.status_code", "ERROR");
+}
+
+pub(crate) fn record_finish_reasons<'a>(span: &Span, reasons: impl IntoIterator<Item = &'a str>) {
+    let reasons: Vec<&str> = reasons.into_iter().collect();
+    if !reasons.is_empty() {
+        span.record(RESPONSE_FINISH_REASONS, format!("{:?}", reasons));
+    }
+}
+
+pub(crate) fn record_time_to_first_token(span: &Span, elapsed: Duration) {
+    span.record(TIME_TO_FIRST_TOKEN, elapsed.as_secs_f64());
+}
+
+fn record_usage(span: &Span, input: u64, output: u64, total: u64) {
+    span.record(USAGE_INPUT_TOKENS, input);
+    span.record(USAGE_OUTPUT_TOKENS, output);
+    span.record(USAGE_TOTAL_TOKENS, total);
+}
+
+fn record_response(span: &Span, id: &str, model: &str) {
+    span.record(RESPONSE_ID, id);
+    span.record(RESPONSE_MODEL, model);
+}
+
+impl GenAiResponse for ChatCompletionResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        record_finish_reasons(span, self.choices.iter().map(|c| c.finish_reason.as_str()));
+        if let Some(usage) = &self.usage {
+            record_usage(span, usage.prompt_tokens as u64, usage.completion_tokens as u64, usage.total_tokens as u64);
+        }
+    }
+}
+
+/// 流式响应只记录 id、model 和 token 用量，finish_reason 由调用方跨数据块汇总
+impl GenAiResponse for ChatCompletionChunkResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        if let Some(usage) = &self.usage {
+            record_usage(span, usage.prompt_tokens as u64, usage.completion_tokens as u64, usage.total_tokens as u64);
+        }
+    }
+}
+
+impl GenAiResponse for VisionLiteResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        record_finish_reasons(span, self.choices.iter().map(|c| c.finish_reason.as_str()));
+        let usage = &self.usage;
+        record_usage(span, usage.prompt_tokens as u64, usage.completion_tokens as u64, usage.total_tokens as u64);
+    }
+}
+
+impl GenAiResponse for VisionProResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        record_finish_reasons(span, self.choices.iter().map(|c| c.finish_reason.as_str()));
+        let usage = &self.usage;
+        record_usage(span, usage.prompt_tokens as u64, usage.completion_tokens as u64, usage.total_tokens as u64);
+    }
+}
+
+impl GenAiResponse for EmbeddingsResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        let usage = &self.usage;
+        record_usage(span, usage.prompt_tokens as u64, 0, usage.total_tokens as u64);
+    }
+}
+
+impl GenAiResponse for MultimodalEmbeddingsResponse {
+    fn record(&self, span: &Span) {
+        record_response(span, &self.id, &self.model);
+        let usage = &self.usage;
+        record_usage(span, usage.prompt_tokens as u64, 0, usage.total_tokens as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chat_request, http_response, mock_sdk, mock_server, CHAT_COMPLETION_RESPONSE};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    /// 把 span 上记录的属性收集起来，便于断言
+    #[derive(Clone, Default)]
+    struct FieldCollector(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for FieldCollector {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.lock().unwrap().insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for FieldCollector {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_completion_span_should_follow_gen_ai_conventions() {
+        let collector = FieldCollector::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(collector.clone()));
+        let (base_url, _) = mock_server(vec![http_response(200, &[], CHAT_COMPLETION_RESPONSE)]).await;
+        mock_sdk(&base_url).chat_completion(&chat_request()).await.unwrap();
+
+        let fields = collector.0.lock().unwrap();
+        assert_eq!(fields[OPERATION_NAME], "chat");
+        assert_eq!(fields[REQUEST_MODEL], chat_request().model());
+        assert_eq!(fields[REQUEST_STREAM], "false");
+        assert_eq!(fields[RESPONSE_ID], "021718067849899d92fcbe0865fdffdde");
+        assert_eq!(fields[RESPONSE_FINISH_REASONS], r#"["stop"]"#);
+        assert_eq!(fields[USAGE_INPUT_TOKENS], "12");
+        assert_eq!(fields[USAGE_OUTPUT_TOKENS], "6");
+        assert_eq!(fields[USAGE_TOTAL_TOKENS], "18");
+        assert!(!fields.contains_key(ERROR_TYPE));
+    }
+
+    #[tokio::test]
+    async fn stream_span_should_record_time_to_first_token_and_errors() {
+        let collector = FieldCollector::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(collector.clone()));
+        let body = concat!(
+            "data: {\"id\":\"0217\",\"model\":\"doubao-pro-32k-240515\",\"object\":\"chat.completion.chunk\",\"created\":1718067849,",
+            "\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"你好\"}}]}\n\n",
+        );
+        let (base_url, _) = mock_server(vec![http_response(200, &[("content-type", "text/event-stream")], body)]).await;
+        let chunks: Vec<_> = futures::StreamExt::collect(
+            mock_sdk(&base_url).chat_completion_chunk_stream(&chat_request()).await.unwrap(),
+        )
+        .await;
+        // 没有收到 [DONE] 就断开了
+        assert!(chunks[0].is_ok() && chunks[1].is_err());
+
+        let fields = collector.0.lock().unwrap();
+        assert_eq!(fields[REQUEST_STREAM], "true");
+        assert_eq!(fields[RESPONSE_ID], "0217");
+        assert!(fields.contains_key(TIME_TO_FIRST_TOKEN));
+        assert_eq!(fields[ERROR_TYPE], "stream");
+    }
+}
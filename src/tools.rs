@@ -0,0 +1,279 @@
+use futures::future::{join_all, BoxFuture};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::api::chat_completion::{
+    ChatCompletionMessage, ChatCompletionRequest, ChatCompletionResponse, FunctionDefinition,
+    MessageToolCall, ToolMessage, ToolParam,
+};
+use crate::error::Result;
+use crate::schema::parse_arguments;
+use crate::LlmSdk;
+
+/// 建议的模型调用轮数上限，避免模型反复调用工具停不下来
+pub const DEFAULT_MAX_ITERATIONS: usize = 8;
+
+type ToolHandler = Arc<dyn Fn(String) -> BoxFuture<'static, std::result::Result<String, String>> + Send + Sync>;
+
+/// 工具注册表，按函数名把模型的工具调用分发给对应的异步处理函数
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, RegisteredTool>,
+}
+
+#[derive(Clone)]
+struct RegisteredTool {
+    definition: FunctionDefinition,
+    handler: ToolHandler,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册一个工具，arguments 会按 JSON 反序列化成 A，返回值序列化后作为 Tool Message 的内容。
+    /// 返回值是字符串时直接使用，不再额外加引号。
+    pub fn register<A, R, E, F, Fut>(&mut self, definition: FunctionDefinition, handler: F) -> &mut Self
+    where
+        A: DeserializeOwned + Send + 'static,
+        R: Serialize,
+        E: fmt::Display,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<R, E>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let name = definition.name.clone();
+        let handler: ToolHandler = Arc::new(move |arguments: String| {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let args: A = parse_arguments(&arguments).map_err(|e| e.to_string())?;
+                let output = handler(args).await.map_err(|e| e.to_string())?;
+                match serde_json::to_value(output).map_err(|e| e.to_string())? {
+                    serde_json::Value::String(text) => Ok(text),
+                    value => Ok(value.to_string()),
+                }
+            })
+        });
+        self.tools.insert(name, RegisteredTool { definition, handler });
+        self
+    }
+
+    /// 用参数类型 A 生成函数声明并注册，声明和解析使用同一个类型
+    #[cfg(feature = "schema")]
+    pub fn register_typed<A, R, E, F, Fut>(
+        &mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        handler: F,
+    ) -> &mut Self
+    where
+        A: DeserializeOwned + schemars::JsonSchema + Send + 'static,
+        R: Serialize,
+        E: fmt::Display,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<R, E>> + Send + 'static,
+    {
+        self.register(FunctionDefinition::from_type::<A>(name, description), handler)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tools.contains_key(name)
+    }
+
+    /// 所有已注册工具的声明，用于 ChatCompletionRequest 的 tools 参数
+    pub fn tools(&self) -> Vec<ToolParam> {
+        self.tools
+            .values()
+            .map(|tool| ToolParam {
+                r#type: "function".to_string(),
+                function: tool.definition.clone(),
+            })
+            .collect()
+    }
+
+    /// 执行一次工具调用。未知工具、参数不合法或者处理函数出错时，
+    /// 把错误以 `{"error": "..."}` 的形式返回给模型，由模型决定如何继续
+    pub async fn call(&self, call: &MessageToolCall) -> ToolMessage {
+        let result = match self.tools.get(&call.function.name) {
+            Some(tool) => (tool.handler)(call.function.arguments.clone()).await,
+            None => Err(format!("unknown tool: {}", call.function.name)),
+        };
+        let content = result.unwrap_or_else(|e| {
+            warn!("tool {} failed: {}", call.function.name, e);
+            serde_json::json!({ "error": e }).to_string()
+        });
+        ToolMessage {
+            content,
+            tool_call_id: call.id.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolRegistry")
+            .field("tools", &self.tools.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// run_with_tools 的结果
+#[derive(Debug, Clone)]
+pub struct ToolRun {
+    /// 最后一次模型调用的响应，达到最大轮数时 finish_reason 仍可能是 tool_calls
+    pub response: ChatCompletionResponse,
+    /// 完整的消息列表，包含中间的 Assistant Message、Tool Message 以及最后的回复
+    pub messages: Vec<ChatCompletionMessage>,
+    /// 调用模型的次数
+    pub iterations: usize,
+}
+
+impl LlmSdk {
+    /// 自动执行工具调用：模型返回 tool_calls 时调用注册的工具，把结果按调用顺序追加成 Tool Message 后再次请求，
+    /// 直到 finish_reason 为 stop、模型不再调用工具，或者达到 max_iterations 次模型调用。
+    /// 请求中没有声明 tools 时使用注册表中的全部工具。
+    /// 至少调用一次模型，max_iterations 为 0 时按 1 处理。
+    pub async fn run_with_tools(
+        &self,
+        req: &ChatCompletionRequest,
+        registry: &ToolRegistry,
+        max_iterations: usize,
+    ) -> Result<ToolRun> {
+        let max_iterations = max_iterations.max(1);
+        let mut req = req.clone();
+        if req.tools.is_none() {
+            req.tools = Some(registry.tools());
+        }
+        let mut iterations = 0;
+        loop {
+            let response = self.chat_completion(&req).await?;
+            iterations += 1;
+            let Some(choice) = response.choices.first() else {
+                return Ok(ToolRun {
+                    response,
+                    messages: req.messages,
+                    iterations,
+                });
+            };
+            req.push_message(ChatCompletionMessage::Assistant((&choice.message).into()));
+            let calls = choice.message.tool_calls.clone().unwrap_or_default();
+            if choice.finish_reason == "stop" || calls.is_empty() || iterations >= max_iterations {
+                return Ok(ToolRun {
+                    response,
+                    messages: req.messages,
+                    iterations,
+                });
+            }
+            // 工具并发执行，结果必须和 tool_calls 的顺序一一对应
+            let results = join_all(calls.iter().map(|call| registry.call(call))).await;
+            for result in results {
+                req.push_message(ChatCompletionMessage::Tool(result));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat_completion::{FunctionDefinitionBuilder, Function};
+    use crate::tests::{chat_request, http_response, mock_sdk, mock_server, CHAT_COMPLETION_RESPONSE};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct WeatherArgs {
+        location: String,
+    }
+
+    fn registry() -> ToolRegistry {
+        let mut registry = ToolRegistry::new();
+        registry.register(
+            FunctionDefinitionBuilder::default()
+                .name("get_current_weather".to_string())
+                .description("获取指定城市的天气".to_string())
+                .build()
+                .unwrap(),
+            |args: WeatherArgs| async move { Ok::<_, String>(format!("{}：晴，25 度", args.location)) },
+        );
+        registry
+    }
+
+    fn tool_call(id: &str, name: &str, arguments: &str) -> MessageToolCall {
+        MessageToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn registry_should_dispatch_and_report_errors() {
+        let registry = registry();
+        assert_eq!(registry.tools()[0].function.name, "get_current_weather");
+
+        let message = registry.call(&tool_call("call_1", "get_current_weather", r#"{"location":"北京"}"#)).await;
+        assert_eq!(message.tool_call_id, "call_1");
+        assert_eq!(message.content, "北京：晴，25 度");
+
+        let message = registry.call(&tool_call("call_2", "get_current_weather", r#"{"city":"北京"}"#)).await;
+        assert!(message.content.contains("missing field `location`"));
+        let message = registry.call(&tool_call("call_3", "book_flight", "{}")).await;
+        assert!(message.content.contains("unknown tool: book_flight"));
+    }
+
+    #[tokio::test]
+    async fn run_with_tools_should_loop_until_stop() {
+        let tool_calls = r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":"","tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"北京\"}"}},{"id":"call_2","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"上海\"}"}}]}}],"usage":{"prompt_tokens":50,"completion_tokens":20,"total_tokens":70}}"#;
+        let (base_url, requests) = mock_server(vec![
+            http_response(200, &[], tool_calls),
+            http_response(200, &[], CHAT_COMPLETION_RESPONSE),
+        ])
+        .await;
+        let run = mock_sdk(&base_url)
+            .run_with_tools(&chat_request(), &registry(), DEFAULT_MAX_ITERATIONS)
+            .await
+            .unwrap();
+        assert_eq!(run.iterations, 2);
+        assert_eq!(run.response.choices[0].finish_reason, "stop");
+        // user、assistant(tool_calls)、2 个 tool、assistant
+        assert_eq!(run.messages.len(), 5);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains(r#""tools":[{"type":"function","function":{"name":"get_current_weather""#));
+        let second = &requests[1];
+        let first_result = second.find(r#""tool_call_id":"call_1""#).unwrap();
+        let second_result = second.find(r#""tool_call_id":"call_2""#).unwrap();
+        assert!(first_result < second_result);
+        assert!(second.contains("上海：晴，25 度"));
+    }
+
+    #[tokio::test]
+    async fn run_with_tools_should_stop_at_max_iterations() {
+        let tool_calls = r#"{"id":"0217","model":"doubao-pro-32k-240515","object":"chat.completion","created":1718067849,"choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":"","tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":"{\"location\":\"北京\"}"}}]}}]}"#;
+        let (base_url, requests) = mock_server(vec![http_response(200, &[], tool_calls)]).await;
+        let run = mock_sdk(&base_url)
+            .run_with_tools(&chat_request(), &registry(), 3)
+            .await
+            .unwrap();
+        assert_eq!(run.iterations, 3);
+        assert_eq!(run.response.choices[0].finish_reason, "tool_calls");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // max_iterations 为 0 时按 1 处理
+        let run = mock_sdk(&base_url)
+            .run_with_tools(&chat_request(), &registry(), 0)
+            .await
+            .unwrap();
+        assert_eq!(run.iterations, 1);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
+}
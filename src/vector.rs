@@ -0,0 +1,196 @@
+//! 向量工具：L2 归一化、点积和余弦相似度、top-k 检索以及 Matryoshka 维度截断。
+//! 内层循环按 LANES 个元素分块累加，方便编译器自动向量化。
+
+use crate::api::embeddings::{Embedding, EmbeddingsResponse};
+use crate::error::{LlmError, Result};
+
+/// 分块累加的宽度，对应 256 位寄存器中的 f32 个数
+const LANES: usize = 8;
+
+/// 点积，两个向量的维度必须相同
+///
+/// # Panics
+///
+/// 两个向量的维度不同时 panic，输入来自外部时先检查维度
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len(), "vectors must have the same dimensions");
+    let mut sums = [0f32; LANES];
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            sums[i] += x[i] * y[i];
+        }
+    }
+    sums.iter().sum::<f32>() + tail
+}
+
+/// L2 范数
+pub fn norm(v: &[f32]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// 原地 L2 归一化，零向量保持不变
+pub fn normalize(v: &mut [f32]) {
+    let norm = norm(v);
+    if norm > 0.0 {
+        let inv = 1.0 / norm;
+        v.iter_mut().for_each(|x| *x *= inv);
+    }
+}
+
+/// 返回归一化后的副本
+pub fn normalized(v: &[f32]) -> Vec<f32> {
+    let mut v = v.to_vec();
+    normalize(&mut v);
+    v
+}
+
+/// 余弦相似度，取值范围 [-1, 1]，任一向量为零向量时返回 0。
+/// 已经归一化的向量直接用 dot 即可，省去两次求范数
+///
+/// # Panics
+///
+/// 两个向量的维度不同时 panic
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = norm(a) * norm(b);
+    if norms > 0.0 {
+        dot(a, b) / norms
+    } else {
+        0.0
+    }
+}
+
+/// Matryoshka 截断：保留前 dims 维后重新归一化，dims 大于原维度时只做归一化。
+/// 只适用于按 Matryoshka 方式训练的模型，比如 doubao-embedding-large 的 2048、1024、512 维
+pub fn truncate(v: &[f32], dims: usize) -> Vec<f32> {
+    normalized(&v[..dims.min(v.len())])
+}
+
+/// 在 candidates 中找出与 query 余弦相似度最高的 k 个，返回 (下标, 相似度)，按相似度从高到低排列
+///
+/// # Panics
+///
+/// 任一候选向量和 query 的维度不同时 panic
+pub fn top_k<V: AsRef<[f32]>>(query: &[f32], candidates: &[V], k: usize) -> Vec<(usize, f32)> {
+    let query_norm = norm(query);
+    let mut scores: Vec<(usize, f32)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            let candidate = candidate.as_ref();
+            let norms = query_norm * norm(candidate);
+            let score = if norms > 0.0 { dot(query, candidate) / norms } else { 0.0 };
+            (i, score)
+        })
+        .collect();
+    let by_score = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1);
+    if k < scores.len() {
+        if k == 0 {
+            return Vec::new();
+        }
+        scores.select_nth_unstable_by(k - 1, by_score);
+        scores.truncate(k);
+    }
+    scores.sort_by(by_score);
+    scores
+}
+
+impl Embedding {
+    /// 与另一条向量的余弦相似度
+    pub fn cosine_similarity(&self, other: &Embedding) -> f32 {
+        cosine_similarity(&self.embedding, &other.embedding)
+    }
+}
+
+impl EmbeddingsResponse {
+    /// 把所有向量原地归一化，之后可以直接用 dot 计算余弦相似度
+    pub fn normalize(&mut self) {
+        self.data.iter_mut().for_each(|embedding| normalize(&mut embedding.embedding));
+    }
+
+    /// 把所有向量截断到 dims 维并重新归一化
+    pub fn truncate(&mut self, dims: usize) {
+        for embedding in &mut self.data {
+            embedding.embedding = truncate(&embedding.embedding, dims);
+        }
+    }
+
+    /// 与 query 余弦相似度最高的 k 条，返回 (index, 相似度)，index 对应请求参数 input 中的位置。
+    /// query 和向量的维度不同时返回 `LlmError::InvalidInput`
+    pub fn top_k(&self, query: &[f32], k: usize) -> Result<Vec<(usize, f32)>> {
+        if let Some(embedding) = self.data.iter().find(|embedding| embedding.embedding.len() != query.len()) {
+            return Err(LlmError::InvalidInput(format!(
+                "query has {} dimensions, embedding {} has {}",
+                query.len(),
+                embedding.index,
+                embedding.embedding.len()
+            )));
+        }
+        let vectors: Vec<&[f32]> = self.data.iter().map(|embedding| embedding.embedding.as_slice()).collect();
+        Ok(top_k(query, &vectors, k)
+            .into_iter()
+            .map(|(i, score)| (self.data[i].index, score))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> EmbeddingsResponse {
+        serde_json::from_str(include_str!("../example.txt")).unwrap()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn similarity_should_match_naive_implementation() {
+        let res = fixture();
+        let (a, b) = (&res.data[0].embedding, &res.data[1].embedding);
+        let naive: f64 = a.iter().zip(b.iter()).map(|(x, y)| *x as f64 * *y as f64).sum();
+        assert!(((dot(a, b) as f64 - naive) / naive).abs() < 1e-4);
+
+        assert_close(cosine_similarity(a, a), 1.0);
+        let cosine = res.data[0].cosine_similarity(&res.data[1]);
+        assert!(cosine > -1.0 && cosine < 1.0);
+        assert_close(dot(&normalized(a), &normalized(b)), cosine);
+        assert_eq!(cosine_similarity(a, &vec![0.0; a.len()]), 0.0);
+
+        // 长度不是 LANES 整数倍时尾部也要参与计算
+        assert_eq!(dot(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]), 32.0);
+    }
+
+    #[test]
+    fn response_should_normalize_truncate_and_search() {
+        let mut res = fixture();
+        let query = res.data[1].embedding.clone();
+        let hits = res.top_k(&query, 2).unwrap();
+        assert_eq!(hits[0].0, 1);
+        assert_close(hits[0].1, 1.0);
+        assert_eq!(hits[1].0, 0);
+        assert_eq!(res.top_k(&query, 1).unwrap().len(), 1);
+        assert!(res.top_k(&query, 0).unwrap().is_empty());
+        // 维度不对时返回错误而不是 panic
+        let err = res.top_k(&query[..512], 2).unwrap_err();
+        assert!(matches!(err, LlmError::InvalidInput(_)));
+
+        res.normalize();
+        assert!(res.vectors().iter().all(|v| (norm(v) - 1.0).abs() < 1e-4));
+
+        res.truncate(512);
+        assert!(res.vectors().iter().all(|v| v.len() == 512 && (norm(v) - 1.0).abs() < 1e-4));
+        let full = fixture();
+        // 先归一化再截断，和直接截断的结果一致
+        assert_close(dot(res.vectors()[0], &truncate(full.vectors()[0], 512)), 1.0);
+    }
+}